@@ -18,6 +18,27 @@ pub trait MyServiceBusPublisherClient {
         message: &[MessageToPublish],
         do_retry: bool,
     ) -> Result<(), PublishError>;
+
+    // Clients which can not ask the server to persist immediately ignore the flag
+    async fn publish_message_with_options(
+        &self,
+        topic_id: &str,
+        message: MessageToPublish,
+        do_retry: bool,
+        _persist_immediately: bool,
+    ) -> Result<(), PublishError> {
+        self.publish_message(topic_id, message, do_retry).await
+    }
+
+    async fn publish_messages_with_options(
+        &self,
+        topic_id: &str,
+        message: &[MessageToPublish],
+        do_retry: bool,
+        _persist_immediately: bool,
+    ) -> Result<(), PublishError> {
+        self.publish_messages(topic_id, message, do_retry).await
+    }
 }
 
 pub trait MyServiceBusSubscriberClient {
@@ -47,6 +68,8 @@ pub trait MyServiceBusSubscriberClient {
         connection_id: i32,
         ok_messages: Vec<crate::queue_with_intervals::QueueIndexRange<i64>>,
     );
+
+    fn deserialization_failed(&self, _topic_id: &str, _queue_id: &str, _messages_amount: usize) {}
 }
 
 #[async_trait::async_trait]
@@ -1,11 +1,44 @@
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum PublishError {
     NoConnectionToPublish,
     SerializationError(String),
     Disconnected,
+    ShuttingDown,
+    Rejected(String),
+    Timeout,
+    InFlightLimitReached,
+    QueueIsFull,
+    MessageTooLarge {
+        size: usize,
+        max_packet_size: usize,
+    },
+    RetriesExhausted {
+        attempts: usize,
+        elapsed: std::time::Duration,
+    },
     Other(String),
 }
 
+impl PublishError {
+    // Publishing the same messages again can not succeed.
+    // Publishes with retries get Rejected only when the client does not reconnect anymore.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            PublishError::SerializationError(_)
+            | PublishError::ShuttingDown
+            | PublishError::Rejected(_)
+            | PublishError::MessageTooLarge { .. } => true,
+            PublishError::NoConnectionToPublish
+            | PublishError::Disconnected
+            | PublishError::Timeout
+            | PublishError::InFlightLimitReached
+            | PublishError::QueueIsFull
+            | PublishError::RetriesExhausted { .. }
+            | PublishError::Other(_) => false,
+        }
+    }
+}
+
 impl Into<PublishError> for String {
     fn into(self) -> PublishError {
         PublishError::Other(self)
@@ -15,3 +15,9 @@ mod serializer;
 pub use serializer::*;
 
 pub extern crate queue_with_intervals;
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+#[cfg(test)]
+mod test_utils;
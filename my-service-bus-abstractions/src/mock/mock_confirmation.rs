@@ -0,0 +1,31 @@
+use crate::{queue_with_intervals::QueueIndexRange, MySbMessage};
+
+#[derive(Debug, Clone)]
+pub enum MockConfirmationType {
+    Delivered,
+    NotDelivered,
+    SomeMessagesOk(Vec<QueueIndexRange<i64>>),
+    Intermediary(Vec<QueueIndexRange<i64>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct MockConfirmation {
+    pub topic_id: String,
+    pub queue_id: String,
+    pub confirmation_id: i64,
+    pub confirmation_type: MockConfirmationType,
+    // Messages of the batch which were not confirmed before
+    pub messages: Vec<MySbMessage>,
+}
+
+impl MockConfirmation {
+    pub fn is_delivered(&self) -> bool {
+        matches!(self.confirmation_type, MockConfirmationType::Delivered)
+    }
+}
+
+pub(crate) fn is_in_ranges(ranges: &[QueueIndexRange<i64>], message_id: i64) -> bool {
+    ranges
+        .iter()
+        .any(|range| range.from_id <= message_id && message_id <= range.to_id)
+}
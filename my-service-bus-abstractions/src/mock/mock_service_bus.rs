@@ -0,0 +1,452 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+
+use crate::{
+    publisher::MessageToPublish, queue_with_intervals::QueueIndexRange, MessageId, MySbMessage,
+    MyServiceBusPublisherClient, MyServiceBusSubscriberClient,
+    MyServiceBusSubscriberClientCallback, PublishError,
+};
+
+use super::{is_in_ranges, MockConfirmation, MockConfirmationType};
+
+pub const MOCK_CONNECTION_ID: i32 = 1;
+
+struct MockBatchInFlight {
+    confirmation_id: i64,
+    messages: Vec<MySbMessage>,
+}
+
+struct MockQueue {
+    callback: Arc<dyn MyServiceBusSubscriberClientCallback + Send + Sync + 'static>,
+    pending: VecDeque<MySbMessage>,
+    in_flight: Option<MockBatchInFlight>,
+}
+
+struct MockServiceBusData {
+    published: HashMap<String, Vec<MySbMessage>>,
+    queues: HashMap<(String, String), MockQueue>,
+    confirmations: Vec<MockConfirmation>,
+    next_message_id: i64,
+    next_confirmation_id: i64,
+}
+
+// In-process bus for unit tests. Each queue gets one batch at a time like with the real broker.
+// Messages which are not confirmed as delivered are redelivered with the next attempt_no.
+// Delivery is asynchronous: publish returns before subscribers get the messages,
+// so tests should use wait_for_confirmations.
+pub struct MockServiceBus {
+    data: Arc<Mutex<MockServiceBusData>>,
+    confirmation_received: Arc<Notify>,
+}
+
+impl MockServiceBus {
+    pub fn new() -> Self {
+        Self {
+            data: Arc::new(Mutex::new(MockServiceBusData {
+                published: HashMap::new(),
+                queues: HashMap::new(),
+                confirmations: Vec::new(),
+                next_message_id: 0,
+                next_confirmation_id: 0,
+            })),
+            confirmation_received: Arc::new(Notify::new()),
+        }
+    }
+
+    // Only messages published after the subscription are delivered to the queue
+    pub fn subscribe(
+        &self,
+        callback: Arc<dyn MyServiceBusSubscriberClientCallback + Send + Sync + 'static>,
+    ) {
+        let mut write_access = self.data.lock();
+        write_access.queues.insert(
+            (
+                callback.get_topic_id().to_string(),
+                callback.get_queue_id().to_string(),
+            ),
+            MockQueue {
+                callback,
+                pending: VecDeque::new(),
+                in_flight: None,
+            },
+        );
+    }
+
+    pub fn get_published_messages(&self, topic_id: &str) -> Vec<MySbMessage> {
+        let read_access = self.data.lock();
+        match read_access.published.get(topic_id) {
+            Some(messages) => messages.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get_confirmations(&self) -> Vec<MockConfirmation> {
+        let read_access = self.data.lock();
+        read_access.confirmations.clone()
+    }
+
+    // Returns all the confirmations even if the timeout is reached before the amount
+    pub async fn wait_for_confirmations(
+        &self,
+        amount: usize,
+        timeout: Duration,
+    ) -> Vec<MockConfirmation> {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        loop {
+            let confirmation_received = self.confirmation_received.notified();
+
+            let confirmations = self.get_confirmations();
+
+            if confirmations.len() >= amount {
+                return confirmations;
+            }
+
+            if tokio::time::timeout_at(deadline, confirmation_received)
+                .await
+                .is_err()
+            {
+                return self.get_confirmations();
+            }
+        }
+    }
+
+    fn publish(&self, topic_id: &str, messages: &[MessageToPublish]) -> Vec<String> {
+        let mut write_access = self.data.lock();
+
+        let mut to_publish = Vec::with_capacity(messages.len());
+
+        for message in messages {
+            to_publish.push(MySbMessage {
+                id: MessageId::new(write_access.next_message_id),
+                attempt_no: 0,
+                headers: message.headers.clone(),
+                content: message.content.clone(),
+            });
+
+            write_access.next_message_id += 1;
+        }
+
+        let mut queues_to_deliver = Vec::new();
+
+        for ((queue_topic_id, queue_id), queue) in write_access.queues.iter_mut() {
+            if queue_topic_id == topic_id {
+                queue.pending.extend(to_publish.iter().cloned());
+                queues_to_deliver.push(queue_id.to_string());
+            }
+        }
+
+        write_access
+            .published
+            .entry(topic_id.to_string())
+            .or_default()
+            .extend(to_publish);
+
+        queues_to_deliver
+    }
+
+    fn confirm(
+        &self,
+        topic_id: &str,
+        queue_id: &str,
+        confirmation_id: i64,
+        confirmation_type: MockConfirmationType,
+    ) {
+        {
+            let mut write_access = self.data.lock();
+
+            let queue = match write_access
+                .queues
+                .get_mut(&(topic_id.to_string(), queue_id.to_string()))
+            {
+                Some(queue) => queue,
+                None => return,
+            };
+
+            let in_flight = match queue.in_flight.take() {
+                Some(in_flight) if in_flight.confirmation_id == confirmation_id => in_flight,
+                other => {
+                    queue.in_flight = other;
+                    return;
+                }
+            };
+
+            let messages = in_flight.messages.clone();
+
+            match &confirmation_type {
+                MockConfirmationType::Delivered => {}
+                MockConfirmationType::NotDelivered => {
+                    redeliver(queue, in_flight.messages);
+                }
+                MockConfirmationType::SomeMessagesOk(delivered) => {
+                    let not_delivered = in_flight
+                        .messages
+                        .into_iter()
+                        .filter(|itm| !is_in_ranges(delivered, itm.id.get_value()))
+                        .collect();
+
+                    redeliver(queue, not_delivered);
+                }
+                MockConfirmationType::Intermediary(delivered) => {
+                    let messages = in_flight
+                        .messages
+                        .into_iter()
+                        .filter(|itm| !is_in_ranges(delivered, itm.id.get_value()))
+                        .collect();
+
+                    queue.in_flight = Some(MockBatchInFlight {
+                        confirmation_id,
+                        messages,
+                    });
+                }
+            }
+
+            write_access.confirmations.push(MockConfirmation {
+                topic_id: topic_id.to_string(),
+                queue_id: queue_id.to_string(),
+                confirmation_id,
+                confirmation_type,
+                messages,
+            });
+        }
+
+        self.confirmation_received.notify_waiters();
+
+        tokio::spawn(deliver(
+            self.data.clone(),
+            topic_id.to_string(),
+            queue_id.to_string(),
+        ));
+    }
+}
+
+fn redeliver(queue: &mut MockQueue, messages: Vec<MySbMessage>) {
+    for mut message in messages.into_iter().rev() {
+        message.attempt_no += 1;
+        queue.pending.push_front(message);
+    }
+}
+
+async fn deliver(data: Arc<Mutex<MockServiceBusData>>, topic_id: String, queue_id: String) {
+    let (callback, messages, confirmation_id) = {
+        let mut write_access = data.lock();
+
+        let confirmation_id = write_access.next_confirmation_id;
+
+        let queue = match write_access
+            .queues
+            .get_mut(&(topic_id.to_string(), queue_id.to_string()))
+        {
+            Some(queue) => queue,
+            None => return,
+        };
+
+        if queue.in_flight.is_some() || queue.pending.len() == 0 {
+            return;
+        }
+
+        let messages: Vec<MySbMessage> = queue.pending.drain(..).collect();
+
+        queue.in_flight = Some(MockBatchInFlight {
+            confirmation_id,
+            messages: messages.clone(),
+        });
+
+        let callback = queue.callback.clone();
+
+        write_access.next_confirmation_id += 1;
+
+        (callback, messages, confirmation_id)
+    };
+
+    callback
+        .new_events(messages, confirmation_id, MOCK_CONNECTION_ID)
+        .await;
+}
+
+#[async_trait::async_trait]
+impl MyServiceBusPublisherClient for MockServiceBus {
+    async fn publish_message(
+        &self,
+        topic_id: &str,
+        message: MessageToPublish,
+        do_retry: bool,
+    ) -> Result<(), PublishError> {
+        self.publish_messages(topic_id, &[message], do_retry).await
+    }
+
+    async fn publish_messages(
+        &self,
+        topic_id: &str,
+        messages: &[MessageToPublish],
+        _do_retry: bool,
+    ) -> Result<(), PublishError> {
+        for queue_id in self.publish(topic_id, messages) {
+            tokio::spawn(deliver(self.data.clone(), topic_id.to_string(), queue_id));
+        }
+
+        Ok(())
+    }
+}
+
+impl MyServiceBusSubscriberClient for MockServiceBus {
+    fn confirm_delivery(
+        &self,
+        topic_id: &str,
+        queue_id: &str,
+        confirmation_id: i64,
+        _connection_id: i32,
+        delivered: bool,
+    ) {
+        let confirmation_type = if delivered {
+            MockConfirmationType::Delivered
+        } else {
+            MockConfirmationType::NotDelivered
+        };
+
+        self.confirm(topic_id, queue_id, confirmation_id, confirmation_type);
+    }
+
+    fn confirm_some_messages_ok(
+        &self,
+        topic_id: &str,
+        queue_id: &str,
+        confirmation_id: i64,
+        _connection_id: i32,
+        ok_messages: Vec<QueueIndexRange<i64>>,
+    ) {
+        self.confirm(
+            topic_id,
+            queue_id,
+            confirmation_id,
+            MockConfirmationType::SomeMessagesOk(ok_messages),
+        );
+    }
+
+    fn intermediary_confirm(
+        &self,
+        topic_id: &str,
+        queue_id: &str,
+        confirmation_id: i64,
+        _connection_id: i32,
+        ok_messages: Vec<QueueIndexRange<i64>>,
+    ) {
+        self.confirm(
+            topic_id,
+            queue_id,
+            confirmation_id,
+            MockConfirmationType::Intermediary(ok_messages),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use crate::{
+        publisher::MessageToPublish,
+        subscriber::{
+            MessagesReader, MySbSubscriberHandleError, Subscriber, SubscriberCallback,
+            TopicQueueType,
+        },
+        test_utils::{TestLogger, TestModel},
+        MyServiceBusPublisherClient,
+    };
+
+    use super::MockServiceBus;
+
+    // Fails the first `attempts_to_fail` batches without reading them
+    struct TestCallback {
+        attempts_to_fail: usize,
+        attempts: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl SubscriberCallback<TestModel> for TestCallback {
+        async fn handle_messages(
+            &self,
+            messages_reader: &MessagesReader<TestModel>,
+        ) -> Result<(), MySbSubscriberHandleError> {
+            if self.attempts.fetch_add(1, Ordering::SeqCst) < self.attempts_to_fail {
+                return Err(MySbSubscriberHandleError::AllMessagesAreNotDelivered);
+            }
+
+            while messages_reader.get_next_message().await.is_some() {}
+
+            Ok(())
+        }
+    }
+
+    fn subscribe(bus: &Arc<MockServiceBus>, attempts_to_fail: usize) {
+        let subscriber = Subscriber::new(
+            "test-topic".into(),
+            "test-queue".into(),
+            TopicQueueType::Permanent,
+            Arc::new(TestCallback {
+                attempts_to_fail,
+                attempts: AtomicUsize::new(0),
+            }),
+            Arc::new(TestLogger),
+            bus.clone(),
+        );
+
+        bus.subscribe(Arc::new(subscriber));
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_delivered() {
+        let bus = Arc::new(MockServiceBus::new());
+        subscribe(&bus, 0);
+
+        bus.publish_messages(
+            "test-topic",
+            &[
+                MessageToPublish::new(vec![1]),
+                MessageToPublish::new(vec![2]),
+            ],
+            false,
+        )
+        .await
+        .unwrap();
+
+        let confirmations = bus.wait_for_confirmations(1, Duration::from_secs(5)).await;
+
+        assert_eq!(1, confirmations.len());
+        assert!(confirmations[0].is_delivered());
+        assert_eq!(2, confirmations[0].messages.len());
+        assert_eq!(2, bus.get_published_messages("test-topic").len());
+    }
+
+    #[tokio::test]
+    async fn test_not_delivered_messages_are_redelivered() {
+        let bus = Arc::new(MockServiceBus::new());
+        subscribe(&bus, 1);
+
+        bus.publish_message("test-topic", MessageToPublish::new(vec![1]), false)
+            .await
+            .unwrap();
+
+        let confirmations = bus.wait_for_confirmations(2, Duration::from_secs(5)).await;
+
+        assert_eq!(2, confirmations.len());
+
+        assert!(!confirmations[0].is_delivered());
+        assert_eq!(0, confirmations[0].messages[0].attempt_no);
+
+        assert!(confirmations[1].is_delivered());
+        assert_eq!(1, confirmations[1].messages[0].attempt_no);
+    }
+}
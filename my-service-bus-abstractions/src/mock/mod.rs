@@ -0,0 +1,4 @@
+mod mock_confirmation;
+mod mock_service_bus;
+pub use mock_confirmation::*;
+pub use mock_service_bus::*;
@@ -12,6 +12,7 @@ pub struct MyServiceBusPublisher<TMessageModel: MySbMessageSerializer> {
     pub topic_id: String,
     pub client: Arc<dyn MyServiceBusPublisherClient + Send + Sync + 'static>,
     pub do_retries: bool,
+    pub persist_immediately: bool,
     pub itm: Option<TMessageModel>,
     pub logger: Arc<dyn Logger + Send + Sync + 'static>,
 }
@@ -27,11 +28,18 @@ impl<TMessageModel: MySbMessageSerializer> MyServiceBusPublisher<TMessageModel>
             topic_id,
             client,
             do_retries,
+            persist_immediately: false,
             logger,
             itm: None,
         }
     }
 
+    // Asks the broker to persist messages before confirming the publish
+    pub fn with_persist_immediately(mut self, persist_immediately: bool) -> Self {
+        self.persist_immediately = persist_immediately;
+        self
+    }
+
     pub async fn publish(
         &self,
         message: &TMessageModel,
@@ -60,10 +68,11 @@ impl<TMessageModel: MySbMessageSerializer> MyServiceBusPublisher<TMessageModel>
 
         let result = self
             .client
-            .publish_message(
+            .publish_message_with_options(
                 &self.topic_id,
                 MessageToPublish { headers, content },
                 self.do_retries,
+                self.persist_immediately,
             )
             .await;
 
@@ -113,10 +122,11 @@ impl<TMessageModel: MySbMessageSerializer> MyServiceBusPublisher<TMessageModel>
 
         let result = self
             .client
-            .publish_message(
+            .publish_message_with_options(
                 &self.topic_id,
                 MessageToPublish { headers, content },
                 self.do_retries,
+                self.persist_immediately,
             )
             .await;
 
@@ -158,7 +168,12 @@ impl<TMessageModel: MySbMessageSerializer> MyServiceBusPublisher<TMessageModel>
 
         let result = self
             .client
-            .publish_messages(&self.topic_id, &messages_to_publish, self.do_retries)
+            .publish_messages_with_options(
+                &self.topic_id,
+                &messages_to_publish,
+                self.do_retries,
+                self.persist_immediately,
+            )
             .await;
 
         if let Err(err) = &result {
@@ -206,7 +221,12 @@ impl<TMessageModel: MySbMessageSerializer> MyServiceBusPublisher<TMessageModel>
 
         let result = self
             .client
-            .publish_messages(&self.topic_id, &messages_to_publish, self.do_retries)
+            .publish_messages_with_options(
+                &self.topic_id,
+                &messages_to_publish,
+                self.do_retries,
+                self.persist_immediately,
+            )
             .await;
 
         if let Err(err) = &result {
@@ -260,7 +280,12 @@ impl<TMessageModel: MySbMessageSerializer> MyServiceBusPublisher<TMessageModel>
 
         let result = self
             .client
-            .publish_messages(&self.topic_id, &messages_to_publish, self.do_retries)
+            .publish_messages_with_options(
+                &self.topic_id,
+                &messages_to_publish,
+                self.do_retries,
+                self.persist_immediately,
+            )
             .await;
 
         if let Err(err) = &result {
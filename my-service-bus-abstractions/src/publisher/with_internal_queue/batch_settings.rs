@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct InternalQueueBatchSettings {
+    // How long to wait for more messages before the batch is published
+    pub linger: Duration,
+    pub max_messages: Option<usize>,
+    pub max_bytes: usize,
+}
+
+impl Default for InternalQueueBatchSettings {
+    fn default() -> Self {
+        Self {
+            linger: Duration::ZERO,
+            max_messages: None,
+            max_bytes: 4_000_000,
+        }
+    }
+}
+
+impl InternalQueueBatchSettings {
+    pub fn is_full(&self, messages_amount: usize, messages_size: usize) -> bool {
+        if messages_size >= self.max_bytes {
+            return true;
+        }
+
+        if let Some(max_messages) = self.max_messages {
+            if messages_amount >= max_messages {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InternalQueueBatchSettings;
+
+    #[test]
+    fn test_batch_is_full() {
+        let settings = InternalQueueBatchSettings {
+            max_messages: Some(500),
+            max_bytes: 1024 * 1024,
+            ..Default::default()
+        };
+
+        assert!(!settings.is_full(499, 1024));
+        assert!(settings.is_full(500, 1024));
+        assert!(settings.is_full(1, 1024 * 1024));
+    }
+}
@@ -1,37 +1,314 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    fs::File,
+    sync::{atomic::AtomicBool, Arc},
+};
 
 use parking_lot::Mutex;
 use rust_extensions::auto_shrink::VecDequeAutoShrink;
+use tokio::sync::{oneshot, Notify};
 
+use crate::{MyServiceBusPublisherClient, PublishError};
 
+use super::{
+    super::MessageToPublish, InternalQueueBatchSettings, InternalQueueLimits, InternalQueueMetrics,
+    MessageReceipt, PublisherSpool, QueueOverflowPolicy,
+};
 
-use crate::MyServiceBusPublisherClient;
-
-use super::super::MessageToPublish;
+pub struct QueuedMessage {
+    pub message: MessageToPublish,
+    pub receipt: MessageReceipt,
+}
 
 pub struct QueueToPublish {
-    pub queue: VecDequeAutoShrink<MessageToPublish>,
-    pub being_published: usize,
+    pub queue: VecDequeAutoShrink<QueuedMessage>,
+    pub queue_size: usize,
+    pub being_published: Vec<MessageReceipt>,
+    pub being_published_size: usize,
+    pub spool: Option<PublisherSpool>,
+    pub limits: InternalQueueLimits,
+    pub dropped_messages: usize,
+    pub batch_settings: InternalQueueBatchSettings,
+    pub metrics: InternalQueueMetrics,
+    next_message_id: u64,
 }
 
 impl QueueToPublish {
     pub fn new() -> Self {
         Self {
             queue: VecDequeAutoShrink::new(32),
-            being_published: 0,
+            queue_size: 0,
+            being_published: Vec::new(),
+            being_published_size: 0,
+            spool: None,
+            limits: InternalQueueLimits::default(),
+            dropped_messages: 0,
+            batch_settings: InternalQueueBatchSettings::default(),
+            metrics: InternalQueueMetrics::default(),
+            next_message_id: 0,
+        }
+    }
+
+    pub fn push_back(
+        &mut self,
+        message: MessageToPublish,
+        receipt: Option<oneshot::Sender<Result<(), PublishError>>>,
+    ) {
+        let receipt = MessageReceipt {
+            id: self.next_message_id,
+            sender: receipt,
+        };
+
+        self.next_message_id += 1;
+        self.queue_size += message.content.len();
+        self.queue.push_back(QueuedMessage { message, receipt });
+    }
+
+    pub fn get_messages_amount(&self) -> usize {
+        self.queue.len() + self.being_published.len()
+    }
+
+    pub fn get_next_message_id(&self) -> u64 {
+        self.next_message_id
+    }
+
+    // Messages are dropped only from the front of the queue, so queued ids have no gaps
+    pub fn get_first_not_published_id(&self) -> u64 {
+        if let Some(receipt) = self.being_published.first() {
+            return receipt.id;
+        }
+
+        self.next_message_id - self.queue.len() as u64
+    }
+
+    fn has_capacity(&self, messages: &[MessageToPublish]) -> bool {
+        let messages_size = messages.iter().map(|itm| itm.content.len()).sum();
+
+        self.limits.has_capacity(
+            self.get_messages_amount(),
+            self.queue_size + self.being_published_size,
+            messages.len(),
+            messages_size,
+        )
+    }
+
+    fn get_spool_file_to_sync(&self) -> Option<Arc<File>> {
+        self.spool.as_ref()?.get_file_to_sync()
+    }
+
+    // Nothing is written and nothing is queued if it fails
+    fn append_to_spool(&mut self, messages: &[MessageToPublish]) -> Result<(), PublishError> {
+        if let Some(spool) = self.spool.as_mut() {
+            if let Err(err) = spool.append(messages) {
+                return Err(PublishError::Other(format!(
+                    "Can not write to spool: {}",
+                    err
+                )));
+            }
         }
+
+        Ok(())
     }
+
+    // Messages which are being published are not dropped
+    fn drop_oldest(&mut self) -> bool {
+        let item = match self.queue.pop_front() {
+            Some(item) => item,
+            None => return false,
+        };
+
+        self.queue_size -= item.message.content.len();
+        self.dropped_messages += 1;
+        item.receipt.complete(Err(PublishError::QueueIsFull));
+
+        if let Some(spool) = self.spool.as_mut() {
+            spool.dropped(self.being_published.len());
+        }
+
+        true
+    }
+
+    fn enqueue_with_drop(
+        &mut self,
+        messages: Vec<MessageToPublish>,
+        mut receipt: Option<oneshot::Sender<Result<(), PublishError>>>,
+        drop_oldest: bool,
+    ) -> Result<(), PublishError> {
+        let last_index = messages.len().saturating_sub(1);
+
+        for (index, message) in messages.into_iter().enumerate() {
+            let receipt = if index == last_index {
+                receipt.take()
+            } else {
+                None
+            };
+
+            let to_publish = std::slice::from_ref(&message);
+
+            while drop_oldest && !self.has_capacity(to_publish) {
+                if !self.drop_oldest() {
+                    break;
+                }
+            }
+
+            if !self.has_capacity(to_publish) {
+                self.dropped_messages += 1;
+
+                if let Some(receipt) = receipt {
+                    let _ = receipt.send(Err(PublishError::QueueIsFull));
+                }
+
+                continue;
+            }
+
+            self.append_to_spool(to_publish)?;
+            self.push_back(message, receipt);
+        }
+
+        Ok(())
+    }
+}
+
+pub enum EnqueueResult {
+    Enqueued,
+    WaitForSpace(Vec<MessageToPublish>),
 }
 
 pub struct PublisherWithInternalQueueData {
     pub topic_id: String,
     pub client: Arc<dyn MyServiceBusPublisherClient + Send + Sync + 'static>,
     pub queue_to_publish: Mutex<QueueToPublish>,
+    pub is_shutting_down: AtomicBool,
+    pub persist_immediately: AtomicBool,
+    pub space_released: Notify,
 
     pub logger: Arc<dyn rust_extensions::Logger + Send + Sync + 'static>,
 }
 
 impl PublisherWithInternalQueueData {
+    pub fn get_queue_size(&self) -> usize {
+        let read_access = self.queue_to_publish.lock();
+        read_access.get_messages_amount()
+    }
+
+    pub fn get_dropped_messages(&self) -> usize {
+        let read_access = self.queue_to_publish.lock();
+        read_access.dropped_messages
+    }
+
+    pub fn get_metrics(&self) -> InternalQueueMetrics {
+        let read_access = self.queue_to_publish.lock();
+        let mut result = read_access.metrics.clone();
+        result.queue_size = read_access.get_messages_amount();
+        result.dropped_messages = read_access.dropped_messages;
+        result
+    }
+
+    pub fn get_linger(&self) -> std::time::Duration {
+        let read_access = self.queue_to_publish.lock();
+        read_access.batch_settings.linger
+    }
+
+    pub fn has_full_batch(&self) -> bool {
+        let read_access = self.queue_to_publish.lock();
+        read_access
+            .batch_settings
+            .is_full(read_access.queue.len(), read_access.queue_size)
+    }
+
+    pub fn shutdown(&self) {
+        self.is_shutting_down
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        self.space_released.notify_waiters();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.is_shutting_down
+            .load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    // The receipt is attached to the last message
+    pub fn enqueue(
+        &self,
+        messages: Vec<MessageToPublish>,
+        receipt: Option<oneshot::Sender<Result<(), PublishError>>>,
+        can_wait: bool,
+    ) -> Result<EnqueueResult, PublishError> {
+        let mut write_access = self.queue_to_publish.lock();
+
+        let overflow_policy = write_access.limits.overflow_policy;
+
+        match overflow_policy {
+            QueueOverflowPolicy::DropOldest | QueueOverflowPolicy::DropNewest => {
+                let dropped_before = write_access.dropped_messages;
+                let result = write_access.enqueue_with_drop(
+                    messages,
+                    receipt,
+                    overflow_policy == QueueOverflowPolicy::DropOldest,
+                );
+                let has_dropped = write_access.dropped_messages > dropped_before;
+                let file_to_sync = write_access.get_spool_file_to_sync();
+                drop(write_access);
+
+                let sync_result = sync_spool(file_to_sync);
+
+                // Lets flush know that dropped messages are not waited for anymore
+                if has_dropped {
+                    self.space_released.notify_waiters();
+                }
+
+                result?;
+                sync_result?;
+                return Ok(EnqueueResult::Enqueued);
+            }
+            QueueOverflowPolicy::Error => {
+                if !write_access.has_capacity(&messages) {
+                    return Err(PublishError::QueueIsFull);
+                }
+            }
+            QueueOverflowPolicy::Wait => {
+                if !write_access.has_capacity(&messages) {
+                    if can_wait {
+                        return Ok(EnqueueResult::WaitForSpace(messages));
+                    }
+
+                    return Err(PublishError::QueueIsFull);
+                }
+            }
+        }
+
+        write_access.append_to_spool(&messages)?;
+
+        let mut receipt = receipt;
+        let last_index = messages.len().saturating_sub(1);
+
+        for (index, message) in messages.into_iter().enumerate() {
+            if index == last_index {
+                write_access.push_back(message, receipt.take());
+            } else {
+                write_access.push_back(message, None);
+            }
+        }
+
+        let file_to_sync = write_access.get_spool_file_to_sync();
+        drop(write_access);
+
+        sync_spool(file_to_sync)?;
+
+        Ok(EnqueueResult::Enqueued)
+    }
+
+    pub fn get_next_message_id(&self) -> u64 {
+        let read_access = self.queue_to_publish.lock();
+        read_access.get_next_message_id()
+    }
+
+    // All the messages with ids below are published or dropped
+    pub fn is_flushed(&self, till_message_id: u64) -> bool {
+        let read_access = self.queue_to_publish.lock();
+        read_access.get_first_not_published_id() >= till_message_id
+    }
+
     pub fn get_messages_to_publish(&self) -> Option<Vec<MessageToPublish>> {
         let mut write_access = self.queue_to_publish.lock();
         if write_access.queue.len() == 0 {
@@ -42,42 +319,116 @@ impl PublisherWithInternalQueueData {
 
         let mut size_to_publish = 0;
 
-        while size_to_publish < 4_000_000 {
-            if let Some(item) = write_access.queue.pop_front() {
-                size_to_publish += item.content.len();
-                result.push(item);
-                write_access.being_published += 1;
-            } else {
+        // At least one message is taken, so zero limits do not make empty batches
+        while let Some(item) = write_access.queue.pop_front() {
+            let size = item.message.content.len();
+            size_to_publish += size;
+            write_access.queue_size -= size;
+            write_access.being_published_size += size;
+            write_access.being_published.push(item.receipt);
+            result.push(item.message);
+
+            if write_access
+                .batch_settings
+                .is_full(result.len(), size_to_publish)
+            {
                 break;
             }
         }
 
+        write_access
+            .metrics
+            .batch_is_formed(result.len(), size_to_publish);
+
         Some(result)
     }
 
     pub fn messages_are_published(&self) {
-        let mut write_access = self.queue_to_publish.lock();
-        write_access.being_published = 0;
+        self.complete_being_published(Ok(()));
     }
 
-    pub async fn publish(&self, to_publish: &[MessageToPublish]) -> bool {
-        let result = self
-            .client
-            .publish_messages(&self.topic_id, &to_publish, true).await;
+    // The batch can not be published, so it is dropped and its receipts fail with the error
+    pub fn messages_are_failed(&self, err: PublishError) {
+        self.complete_being_published(Err(err));
+    }
+
+    fn complete_being_published(&self, result: Result<(), PublishError>) {
+        let mut write_access = self.queue_to_publish.lock();
+        let being_published = std::mem::take(&mut write_access.being_published);
+        write_access.being_published_size = 0;
 
-        match result {
-            Ok(_) => return true,
-            Err(err) => {
+        if result.is_err() {
+            write_access.dropped_messages += being_published.len();
+        }
+
+        if let Some(spool) = write_access.spool.as_mut() {
+            if let Err(err) = spool.published(being_published.len()) {
                 let mut ctx = HashMap::new();
                 ctx.insert("topicId".to_string(), self.topic_id.to_string());
-                self.logger.write_fatal_error(
-                    "publish".to_string(),
-                    format!("Can not publish: Err: {:?}", err),
+                self.logger.write_error(
+                    "messages_are_published".to_string(),
+                    format!("Can not write to spool. Err: {}", err),
                     Some(ctx),
                 );
-
-                return false;
             }
         }
+
+        let file_to_sync = write_access.get_spool_file_to_sync();
+        drop(write_access);
+
+        // A not synced ack only makes the messages be replayed after an OS crash
+        if let Err(err) = sync_spool(file_to_sync) {
+            let mut ctx = HashMap::new();
+            ctx.insert("topicId".to_string(), self.topic_id.to_string());
+            self.logger.write_error(
+                "messages_are_published".to_string(),
+                format!("{:?}", err),
+                Some(ctx),
+            );
+        }
+
+        for receipt in being_published {
+            receipt.complete(result.clone());
+        }
+
+        self.space_released.notify_waiters();
+    }
+
+    pub async fn publish(&self, to_publish: &[MessageToPublish]) -> Result<(), PublishError> {
+        let persist_immediately = self
+            .persist_immediately
+            .load(std::sync::atomic::Ordering::SeqCst);
+
+        let result = self
+            .client
+            .publish_messages_with_options(&self.topic_id, &to_publish, true, persist_immediately)
+            .await;
+
+        if let Err(err) = &result {
+            let mut ctx = HashMap::new();
+            ctx.insert("topicId".to_string(), self.topic_id.to_string());
+            self.logger.write_fatal_error(
+                "publish".to_string(),
+                format!("Can not publish: Err: {:?}", err),
+                Some(ctx),
+            );
+        }
+
+        result
     }
 }
+
+// Runs without the queue lock. On error the messages are queued already,
+// but the caller is told that they may be lost on an OS crash.
+fn sync_spool(file: Option<Arc<File>>) -> Result<(), PublishError> {
+    let file = match file {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+
+    if let Err(err) = file.sync_data() {
+        return Err(PublishError::Other(format!("Can not sync spool: {}", err)));
+    }
+
+    Ok(())
+}
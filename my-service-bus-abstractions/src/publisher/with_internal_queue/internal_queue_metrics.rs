@@ -0,0 +1,65 @@
+pub const BATCH_MESSAGES_BUCKETS: [usize; 10] =
+    [1, 5, 10, 50, 100, 500, 1_000, 5_000, 10_000, 50_000];
+
+#[derive(Debug, Clone, Default)]
+pub struct InternalQueueMetrics {
+    pub queue_size: usize,
+    pub dropped_messages: usize,
+    pub batches: u64,
+    pub batch_messages_sum: u64,
+    pub batch_bytes_sum: u64,
+    // Batches which fit into the bucket and do not fit into the previous one.
+    // Batches bigger than the last bucket are counted only in batches.
+    pub batch_messages_buckets: [u64; BATCH_MESSAGES_BUCKETS.len()],
+}
+
+impl InternalQueueMetrics {
+    pub fn batch_is_formed(&mut self, messages_amount: usize, messages_size: usize) {
+        self.batches += 1;
+        self.batch_messages_sum += messages_amount as u64;
+        self.batch_bytes_sum += messages_size as u64;
+
+        if let Some(index) = BATCH_MESSAGES_BUCKETS
+            .iter()
+            .position(|bucket| messages_amount <= *bucket)
+        {
+            self.batch_messages_buckets[index] += 1;
+        }
+    }
+
+    // Several publishers of the same topic are reported together
+    pub fn merge(&mut self, other: &InternalQueueMetrics) {
+        self.queue_size += other.queue_size;
+        self.dropped_messages += other.dropped_messages;
+        self.batches += other.batches;
+        self.batch_messages_sum += other.batch_messages_sum;
+        self.batch_bytes_sum += other.batch_bytes_sum;
+
+        for (index, value) in other.batch_messages_buckets.iter().enumerate() {
+            self.batch_messages_buckets[index] += value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InternalQueueMetrics;
+
+    #[test]
+    fn test_batches_are_put_to_buckets() {
+        let mut metrics = InternalQueueMetrics::default();
+
+        metrics.batch_is_formed(1, 10);
+        metrics.batch_is_formed(5, 50);
+        metrics.batch_is_formed(6, 60);
+        metrics.batch_is_formed(100_000, 1_000_000);
+
+        assert_eq!(4, metrics.batches);
+        assert_eq!(100_012, metrics.batch_messages_sum);
+        assert_eq!(1_000_120, metrics.batch_bytes_sum);
+        assert_eq!(
+            [1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+            metrics.batch_messages_buckets
+        );
+    }
+}
@@ -1,4 +1,14 @@
+mod batch_settings;
 mod data;
+mod internal_queue_metrics;
 mod publisher_with_internal_queue;
+mod publish_receipt;
+mod queue_limits;
+mod spool;
+pub use batch_settings::*;
 pub use data::*;
+pub use internal_queue_metrics::*;
 pub use publisher_with_internal_queue::*;
+pub use publish_receipt::*;
+pub use queue_limits::*;
+pub use spool::*;
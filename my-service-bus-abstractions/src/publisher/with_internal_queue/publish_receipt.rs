@@ -0,0 +1,67 @@
+use tokio::sync::oneshot;
+
+use crate::PublishError;
+
+pub struct MessageReceipt {
+    pub id: u64,
+    pub sender: Option<oneshot::Sender<Result<(), PublishError>>>,
+}
+
+impl MessageReceipt {
+    pub fn complete(self, result: Result<(), PublishError>) {
+        if let Some(sender) = self.sender {
+            let _ = sender.send(result);
+        }
+    }
+}
+
+// Completes when the message is confirmed by the broker or dropped from the queue
+pub struct PublishReceipt {
+    receiver: oneshot::Receiver<Result<(), PublishError>>,
+}
+
+impl PublishReceipt {
+    pub fn new() -> (oneshot::Sender<Result<(), PublishError>>, Self) {
+        let (sender, receiver) = oneshot::channel();
+        (sender, Self { receiver })
+    }
+
+    pub async fn wait(self) -> Result<(), PublishError> {
+        match self.receiver.await {
+            Ok(result) => result,
+            // The publisher is dropped before the message is published
+            Err(_) => Err(PublishError::ShuttingDown),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PublishError;
+
+    use super::{MessageReceipt, PublishReceipt};
+
+    #[tokio::test]
+    async fn test_receipt_is_completed() {
+        let (sender, receipt) = PublishReceipt::new();
+
+        MessageReceipt {
+            id: 0,
+            sender: Some(sender),
+        }
+        .complete(Ok(()));
+
+        assert!(receipt.wait().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_receipt_fails() {
+        let (sender, receipt) = PublishReceipt::new();
+        drop(sender);
+
+        assert!(matches!(
+            receipt.wait().await,
+            Err(PublishError::ShuttingDown)
+        ));
+    }
+}
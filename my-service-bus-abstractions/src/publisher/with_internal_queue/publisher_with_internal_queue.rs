@@ -1,4 +1,7 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicBool, Arc},
+};
 
 #[cfg(feature = "with-telemetry")]
 use my_telemetry::MyTelemetryContext;
@@ -6,11 +9,16 @@ use my_telemetry::MyTelemetryContext;
 use parking_lot::Mutex;
 use tokio::sync::{
     mpsc::{UnboundedReceiver, UnboundedSender},
+    oneshot, Notify,
 };
 
 use crate::{MySbMessageSerializer, MyServiceBusPublisherClient, PublishError};
 
-use super::{super::MessageToPublish, PublisherWithInternalQueueData, QueueToPublish};
+use super::{
+    super::MessageToPublish, EnqueueResult, InternalQueueBatchSettings, InternalQueueLimits,
+    InternalQueueMetrics, PublishReceipt, PublisherSpool, PublisherSpoolSettings,
+    PublisherWithInternalQueueData, QueueToPublish,
+};
 
 pub struct PublisherWithInternalQueue<TMessageModel: MySbMessageSerializer> {
     data: Arc<PublisherWithInternalQueueData>,
@@ -30,6 +38,9 @@ impl<TMessageModel: MySbMessageSerializer> PublisherWithInternalQueue<TMessageMo
             client,
             topic_id,
             queue_to_publish: Mutex::new(QueueToPublish::new()),
+            is_shutting_down: AtomicBool::new(false),
+            persist_immediately: AtomicBool::new(false),
+            space_released: Notify::new(),
             logger,
         };
 
@@ -45,11 +56,119 @@ impl<TMessageModel: MySbMessageSerializer> PublisherWithInternalQueue<TMessageMo
         result
     }
 
+    // Asks the broker to persist messages before confirming the publish
+    pub fn with_persist_immediately(self, persist_immediately: bool) -> Self {
+        self.data
+            .persist_immediately
+            .store(persist_immediately, std::sync::atomic::Ordering::SeqCst);
+        self
+    }
+
+    // Limits the messages which are queued or being published. Not limited by default.
+    pub fn with_queue_limits(self, limits: InternalQueueLimits) -> Self {
+        let mut write_access = self.data.queue_to_publish.lock();
+        write_access.limits = limits;
+        drop(write_access);
+        self
+    }
+
+    // Cuts batches by messages amount and size. With linger the batch waits for more messages.
+    pub fn with_batch_settings(self, batch_settings: InternalQueueBatchSettings) -> Self {
+        let mut write_access = self.data.queue_to_publish.lock();
+        write_access.batch_settings = batch_settings;
+        drop(write_access);
+        self
+    }
+
+    // Writes messages to disk before they are queued and replays not published ones on start.
+    // Must be called before anything is published.
+    pub fn with_spool(self, settings: PublisherSpoolSettings) -> std::io::Result<Self> {
+        // Checked before the spool is opened, since opening rewrites its files.
+        // The lock is held, so nothing is published in between.
+        let mut write_access = self.data.queue_to_publish.lock();
+
+        if write_access.get_messages_amount() > 0 || write_access.spool.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "Spool must be set once and before messages are published",
+            ));
+        }
+
+        let (spool, replayed) = PublisherSpool::open(settings, self.data.topic_id.as_str())?;
+
+        let has_replayed = replayed.len() > 0;
+
+        // Replayed messages are not checked against the queue limits
+        for message in replayed {
+            write_access.push_back(message, None);
+        }
+
+        write_access.spool = Some(spool);
+
+        drop(write_access);
+
+        if has_replayed {
+            let _ = self.event_sender.send(());
+        }
+
+        Ok(self)
+    }
+
     pub fn publish_and_forget(
         &self,
         message: TMessageModel,
         #[cfg(feature = "with-telemetry")] telemetry_context: Option<&MyTelemetryContext>,
     ) -> Result<(), PublishError> {
+        self.enqueue_message(
+            message,
+            None,
+            #[cfg(feature = "with-telemetry")]
+            telemetry_context,
+        )
+    }
+
+    // Same as publish_and_forget, but with QueueOverflowPolicy::Wait it waits for space instead of failing
+    pub async fn publish_and_forget_async(
+        &self,
+        message: TMessageModel,
+        #[cfg(feature = "with-telemetry")] telemetry_context: Option<&MyTelemetryContext>,
+    ) -> Result<(), PublishError> {
+        self.publish_chunk_and_forget(
+            vec![message],
+            #[cfg(feature = "with-telemetry")]
+            telemetry_context,
+        )
+        .await
+    }
+
+    // Same as publish_and_forget, but the receipt lets to wait until the message is confirmed
+    pub fn publish_with_receipt(
+        &self,
+        message: TMessageModel,
+        #[cfg(feature = "with-telemetry")] telemetry_context: Option<&MyTelemetryContext>,
+    ) -> Result<PublishReceipt, PublishError> {
+        let (sender, receipt) = PublishReceipt::new();
+
+        self.enqueue_message(
+            message,
+            Some(sender),
+            #[cfg(feature = "with-telemetry")]
+            telemetry_context,
+        )?;
+
+        Ok(receipt)
+    }
+
+    fn enqueue_message(
+        &self,
+        message: TMessageModel,
+        receipt: Option<oneshot::Sender<Result<(), PublishError>>>,
+        #[cfg(feature = "with-telemetry")] telemetry_context: Option<&MyTelemetryContext>,
+    ) -> Result<(), PublishError> {
+        if self.data.is_shutting_down() {
+            return Err(PublishError::ShuttingDown);
+        }
+
         let result = message.serialize(None);
 
         if let Err(err) = result {
@@ -67,13 +186,18 @@ impl<TMessageModel: MySbMessageSerializer> PublisherWithInternalQueue<TMessageMo
             super::super::my_telemetry::apply_publish_telemetry(&mut headers, my_telemetry)
         }
 
-        let mut write_access = self.data.queue_to_publish.lock();
-        write_access
-            .queue
-            .push_back(MessageToPublish { headers, content });
+        let result = self
+            .data
+            .enqueue(vec![MessageToPublish { headers, content }], receipt, false);
 
-        drop(write_access);
+        // A failed spool sync leaves the message queued, so it is published anyway
+        self.wake_up_publisher();
+
+        result?;
+        Ok(())
+    }
 
+    fn wake_up_publisher(&self) {
         if let Err(err) = self.event_sender.send(()) {
             let mut ctx = HashMap::new();
             ctx.insert("topicId".to_string(), self.data.topic_id.to_string());
@@ -83,8 +207,6 @@ impl<TMessageModel: MySbMessageSerializer> PublisherWithInternalQueue<TMessageMo
                 Some(ctx),
             )
         }
-
-        Ok(())
     }
 
     pub async fn publish_chunk_and_forget(
@@ -92,6 +214,10 @@ impl<TMessageModel: MySbMessageSerializer> PublisherWithInternalQueue<TMessageMo
         messages: Vec<TMessageModel>,
         #[cfg(feature = "with-telemetry")] telemetry_context: Option<&MyTelemetryContext>,
     ) -> Result<(), PublishError> {
+        if self.data.is_shutting_down() {
+            return Err(PublishError::ShuttingDown);
+        }
+
         let mut to_publish = Vec::with_capacity(messages.len());
 
         for message in messages {
@@ -116,28 +242,59 @@ impl<TMessageModel: MySbMessageSerializer> PublisherWithInternalQueue<TMessageMo
             to_publish.push(msg_to_publish);
         }
 
-        let mut write_access = self.data.queue_to_publish.lock();
-        for msg in to_publish {
-            write_access.queue.push_back(msg);
-        }
+        loop {
+            let space_released = self.data.space_released.notified();
 
-        drop(write_access);
+            if self.data.is_shutting_down() {
+                return Err(PublishError::ShuttingDown);
+            }
 
-        if let Err(err) = self.event_sender.send(()) {
-            let mut ctx = HashMap::new();
-            ctx.insert("topicId".to_string(), self.data.topic_id.to_string());
-            self.data.logger.write_error(
-                "publish_and_forget".to_string(),
-                format!("Can not publish message. Err: {}", err),
-                Some(ctx),
-            )
+            match self.data.enqueue(to_publish, None, true) {
+                Ok(EnqueueResult::Enqueued) => break,
+                Ok(EnqueueResult::WaitForSpace(messages)) => {
+                    to_publish = messages;
+                    space_released.await;
+                }
+                Err(err) => {
+                    self.wake_up_publisher();
+                    return Err(err);
+                }
+            }
         }
 
+        self.wake_up_publisher();
+
         Ok(())
     }
+    // Resolves when all the messages enqueued before the call are confirmed by the broker or dropped
+    pub async fn flush(&self) {
+        let till_message_id = self.data.get_next_message_id();
+
+        loop {
+            let space_released = self.data.space_released.notified();
+
+            if self.data.is_flushed(till_message_id) {
+                return;
+            }
+
+            space_released.await;
+        }
+    }
+
     pub fn get_queue_size(&self) -> usize {
-        let read_access = self.data.queue_to_publish.lock();
-        read_access.queue.len() + read_access.being_published
+        self.data.get_queue_size()
+    }
+
+    pub fn get_dropped_messages(&self) -> usize {
+        self.data.get_dropped_messages()
+    }
+
+    pub fn get_metrics(&self) -> InternalQueueMetrics {
+        self.data.get_metrics()
+    }
+
+    pub fn get_data(&self) -> Arc<PublisherWithInternalQueueData> {
+        self.data.clone()
     }
 }
 
@@ -148,7 +305,15 @@ async fn events_publisher(
     let mut to_publish = None;
     loop {
         if to_publish.is_none() {
-            tokio::sync::mpsc::UnboundedReceiver::recv(&mut event_receiver).await;
+            // Messages left after the previous batch are published without waiting for a new event
+            if data.get_queue_size() == 0 {
+                // The publisher is dropped and everything is published
+                if event_receiver.recv().await.is_none() {
+                    return;
+                }
+            }
+
+            linger(&data, &mut event_receiver).await;
             to_publish = data.get_messages_to_publish();
         }
 
@@ -156,11 +321,107 @@ async fn events_publisher(
             continue;
         }
 
-        if data.publish(to_publish.as_ref().unwrap()).await {
-            data.messages_are_published();
-            to_publish = None;
-        } else {
-            tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        match data.publish(to_publish.as_ref().unwrap()).await {
+            Ok(_) => {
+                data.messages_are_published();
+                to_publish = None;
+            }
+            Err(err) if err.is_permanent() => {
+                data.messages_are_failed(err);
+                to_publish = None;
+            }
+            Err(_) => {
+                tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+            }
         }
     }
 }
+
+// Waits for more messages until the batch is full or the linger time is over
+async fn linger(data: &PublisherWithInternalQueueData, event_receiver: &mut UnboundedReceiver<()>) {
+    let linger = data.get_linger();
+
+    if linger.is_zero() {
+        return;
+    }
+
+    let deadline = tokio::time::Instant::now() + linger;
+
+    while !data.has_full_batch() && !data.is_shutting_down() {
+        match tokio::time::timeout_at(deadline, event_receiver.recv()).await {
+            Ok(Some(_)) => {}
+            _ => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        mock::MockServiceBus,
+        publisher::{InternalQueueBatchSettings, PublisherSpoolSettings},
+        test_utils::{TestLogger, TestModel},
+    };
+
+    use super::PublisherWithInternalQueue;
+
+    #[tokio::test]
+    async fn test_spool_is_not_opened_for_a_used_queue() {
+        let path = std::env::temp_dir().join(format!("my-sb-used-queue-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+
+        let publisher = PublisherWithInternalQueue::<TestModel>::new(
+            "test-topic".to_string(),
+            Arc::new(MockServiceBus::new()),
+            Arc::new(TestLogger),
+        );
+
+        publisher
+            .publish_and_forget(
+                TestModel(vec![1]),
+                #[cfg(feature = "with-telemetry")]
+                None,
+            )
+            .unwrap();
+
+        let result = publisher.with_spool(PublisherSpoolSettings::new(&path));
+
+        assert!(result.is_err());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_zero_batch_limits_publish_one_message_per_batch() {
+        let bus = Arc::new(MockServiceBus::new());
+
+        let publisher = PublisherWithInternalQueue::<TestModel>::new(
+            "test-topic".to_string(),
+            bus.clone(),
+            Arc::new(TestLogger),
+        )
+        .with_batch_settings(InternalQueueBatchSettings {
+            max_messages: Some(0),
+            max_bytes: 0,
+            ..Default::default()
+        });
+
+        for i in 0..3 {
+            publisher
+                .publish_and_forget(
+                    TestModel(vec![i]),
+                    #[cfg(feature = "with-telemetry")]
+                    None,
+                )
+                .unwrap();
+        }
+
+        tokio::time::timeout(Duration::from_secs(5), publisher.flush())
+            .await
+            .unwrap();
+
+        assert_eq!(3, bus.get_published_messages("test-topic").len());
+        assert_eq!(3, publisher.get_metrics().batches);
+    }
+}
@@ -0,0 +1,91 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOverflowPolicy {
+    // publish_and_forget fails with PublishError::QueueIsFull
+    Error,
+    DropOldest,
+    DropNewest,
+    // publish_and_forget_async and publish_chunk_and_forget wait for space.
+    // The sync publish_and_forget and publish_with_receipt can not wait and fail with PublishError::QueueIsFull
+    Wait,
+}
+
+#[derive(Debug, Clone)]
+pub struct InternalQueueLimits {
+    pub max_messages: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub overflow_policy: QueueOverflowPolicy,
+}
+
+impl Default for InternalQueueLimits {
+    fn default() -> Self {
+        Self {
+            max_messages: None,
+            max_bytes: None,
+            overflow_policy: QueueOverflowPolicy::Error,
+        }
+    }
+}
+
+impl InternalQueueLimits {
+    // An empty queue always accepts messages, so a chunk bigger than the limits can not get stuck.
+    pub fn has_capacity(
+        &self,
+        messages_in_queue: usize,
+        bytes_in_queue: usize,
+        messages_amount: usize,
+        messages_size: usize,
+    ) -> bool {
+        if messages_in_queue == 0 {
+            return true;
+        }
+
+        if let Some(max_messages) = self.max_messages {
+            if messages_in_queue + messages_amount > max_messages {
+                return false;
+            }
+        }
+
+        if let Some(max_bytes) = self.max_bytes {
+            if bytes_in_queue + messages_size > max_bytes {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InternalQueueLimits;
+
+    #[test]
+    fn test_no_limits_by_default() {
+        let limits = InternalQueueLimits::default();
+        assert!(limits.has_capacity(1_000_000, usize::MAX / 2, 1_000, 1024));
+    }
+
+    #[test]
+    fn test_max_messages() {
+        let limits = InternalQueueLimits {
+            max_messages: Some(3),
+            ..Default::default()
+        };
+
+        assert!(limits.has_capacity(1, 0, 2, 10));
+        assert!(!limits.has_capacity(2, 0, 2, 10));
+        assert!(limits.has_capacity(0, 0, 10, 10));
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let limits = InternalQueueLimits {
+            max_bytes: Some(100),
+            ..Default::default()
+        };
+
+        assert!(limits.has_capacity(1, 50, 1, 50));
+        assert!(!limits.has_capacity(1, 50, 1, 51));
+        assert!(limits.has_capacity(0, 0, 1, 1_000));
+    }
+}
@@ -0,0 +1,568 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use crate::{publisher::MessageToPublish, SbMessageHeaders};
+
+const MESSAGE_RECORD: u8 = 1;
+const ACK_RECORD: u8 = 2;
+
+const SEGMENT_FILE_EXTENSION: &str = "spool";
+
+#[derive(Debug, Clone)]
+pub struct PublisherSpoolSettings {
+    pub path: PathBuf,
+    pub max_segment_size: u64,
+    // Without it the spool survives a process crash, but not an OS crash or a power loss.
+    // The sync is done after the queue lock is released.
+    pub sync_on_write: bool,
+}
+
+impl PublisherSpoolSettings {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_segment_size: 64 * 1024 * 1024,
+            sync_on_write: false,
+        }
+    }
+}
+
+struct SpoolSegment {
+    segment_no: u64,
+    path: PathBuf,
+    last_seq: Option<u64>,
+}
+
+struct ActiveSegment {
+    file: Arc<File>,
+    size: u64,
+}
+
+// Append-only files with messages and acks. Messages are acked in the order they are appended.
+pub struct PublisherSpool {
+    settings: PublisherSpoolSettings,
+    path: PathBuf,
+    segments: VecDeque<SpoolSegment>,
+    active_segment: ActiveSegment,
+    next_seq: u64,
+    not_acked: VecDeque<u64>,
+}
+
+impl PublisherSpool {
+    // Returns the spool and the messages which were not published before the restart.
+    // They are rewritten to a new segment and the old segments are deleted.
+    // A crash in between leaves the messages in both, so they are deduplicated by seq.
+    pub fn open(
+        settings: PublisherSpoolSettings,
+        topic_id: &str,
+    ) -> std::io::Result<(Self, Vec<MessageToPublish>)> {
+        let path = settings.path.join(topic_id);
+        std::fs::create_dir_all(&path)?;
+
+        let old_segments = read_segment_paths(&path)?;
+
+        let mut messages = BTreeMap::new();
+        let mut acked_seq = None;
+        let mut next_seq = 0;
+
+        for (_, segment_path) in old_segments.iter() {
+            let mut content = Vec::new();
+            File::open(segment_path)?.read_to_end(&mut content)?;
+
+            for record in read_records(&content) {
+                match record {
+                    SpoolRecord::Message(seq, message) => {
+                        next_seq = next_seq.max(seq + 1);
+                        messages.insert(seq, message);
+                    }
+                    SpoolRecord::Ack(seq) => {
+                        acked_seq = Some(acked_seq.unwrap_or(0).max(seq));
+                    }
+                }
+            }
+        }
+
+        if let Some(acked_seq) = acked_seq {
+            messages.retain(|seq, _| *seq > acked_seq);
+        }
+
+        let segment_no = match old_segments.last() {
+            Some((segment_no, _)) => segment_no + 1,
+            None => 0,
+        };
+
+        let segment_path = get_segment_path(&path, segment_no);
+
+        let mut result = Self {
+            active_segment: open_segment(&segment_path)?,
+            segments: VecDeque::new(),
+            settings,
+            path,
+            next_seq,
+            not_acked: VecDeque::new(),
+        };
+
+        result.segments.push_back(SpoolSegment {
+            segment_no,
+            path: segment_path,
+            last_seq: None,
+        });
+
+        for (seq, message) in messages.iter() {
+            let mut record = Vec::new();
+            serialize_message(&mut record, *seq, message);
+            result.write_record(&record)?;
+            result.message_is_written(*seq);
+        }
+
+        result.sync()?;
+
+        for (_, segment_path) in old_segments {
+            std::fs::remove_file(segment_path)?;
+        }
+
+        Ok((result, messages.into_values().collect()))
+    }
+
+    // All or nothing: on error the file is truncated back and the spool state is not changed
+    pub fn append(&mut self, messages: &[MessageToPublish]) -> std::io::Result<()> {
+        let mut record = Vec::new();
+
+        for (index, message) in messages.iter().enumerate() {
+            serialize_message(&mut record, self.next_seq + index as u64, message);
+        }
+
+        self.write_record(&record)?;
+
+        for _ in messages {
+            self.message_is_written(self.next_seq);
+            self.next_seq += 1;
+        }
+
+        Ok(())
+    }
+
+    // Returns the file to sync outside of the queue lock if sync_on_write is set
+    pub fn get_file_to_sync(&self) -> Option<Arc<File>> {
+        if self.settings.sync_on_write {
+            Some(self.active_segment.file.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn published(&mut self, messages_amount: usize) -> std::io::Result<()> {
+        let mut last_acked = None;
+
+        for _ in 0..messages_amount {
+            if let Some(seq) = self.not_acked.pop_front() {
+                last_acked = Some(seq);
+            }
+        }
+
+        let last_acked = match last_acked {
+            Some(last_acked) => last_acked,
+            None => return Ok(()),
+        };
+
+        let mut record = vec![ACK_RECORD];
+        record.extend_from_slice(&last_acked.to_le_bytes());
+        self.write_record(&record)?;
+
+        while self.segments.len() > 1 {
+            let segment = self.segments.front().unwrap();
+
+            if let Some(last_seq) = segment.last_seq {
+                if last_seq > last_acked {
+                    break;
+                }
+            }
+
+            let segment = self.segments.pop_front().unwrap();
+            std::fs::remove_file(segment.path)?;
+        }
+
+        Ok(())
+    }
+
+    // Removes a message which was dropped from the queue. The next ack record covers it.
+    pub fn dropped(&mut self, index: usize) {
+        self.not_acked.remove(index);
+    }
+
+    pub fn get_not_acked_amount(&self) -> usize {
+        self.not_acked.len()
+    }
+
+    fn message_is_written(&mut self, seq: u64) {
+        self.segments.back_mut().unwrap().last_seq = Some(seq);
+        self.not_acked.push_back(seq);
+    }
+
+    fn write_record(&mut self, record: &[u8]) -> std::io::Result<()> {
+        if self.active_segment.size > 0
+            && self.active_segment.size + record.len() as u64 > self.settings.max_segment_size
+        {
+            self.rotate()?;
+        }
+
+        let mut file: &File = &self.active_segment.file;
+
+        if let Err(err) = file.write_all(record) {
+            // A partly written record would be read as the end of the segment and hide the next ones
+            let _ = self.active_segment.file.set_len(self.active_segment.size);
+            return Err(err);
+        }
+
+        self.active_segment.size += record.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.sync()?;
+
+        let segment_no = self.segments.back().unwrap().segment_no + 1;
+        let segment_path = get_segment_path(&self.path, segment_no);
+
+        self.active_segment = open_segment(&segment_path)?;
+        self.segments.push_back(SpoolSegment {
+            segment_no,
+            path: segment_path,
+            last_seq: None,
+        });
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.active_segment.file.sync_data()
+    }
+}
+
+enum SpoolRecord {
+    Message(u64, MessageToPublish),
+    Ack(u64),
+}
+
+// A record cut by a crash ends the segment
+fn read_records(content: &[u8]) -> Vec<SpoolRecord> {
+    let mut result = Vec::new();
+    let mut reader = SpoolReader { content, pos: 0 };
+
+    while let Some(record) = reader.read_record() {
+        result.push(record);
+    }
+
+    result
+}
+
+struct SpoolReader<'s> {
+    content: &'s [u8],
+    pos: usize,
+}
+
+impl<'s> SpoolReader<'s> {
+    fn read_slice(&mut self, size: usize) -> Option<&'s [u8]> {
+        if self.pos + size > self.content.len() {
+            return None;
+        }
+
+        let result = &self.content[self.pos..self.pos + size];
+        self.pos += size;
+        Some(result)
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        let result = self.read_slice(4)?;
+        Some(u32::from_le_bytes(result.try_into().ok()?))
+    }
+
+    fn read_u64(&mut self) -> Option<u64> {
+        let result = self.read_slice(8)?;
+        Some(u64::from_le_bytes(result.try_into().ok()?))
+    }
+
+    fn read_bytes(&mut self) -> Option<&'s [u8]> {
+        let size = self.read_u32()? as usize;
+        self.read_slice(size)
+    }
+
+    fn read_string(&mut self) -> Option<String> {
+        let result = self.read_bytes()?;
+        Some(String::from_utf8_lossy(result).to_string())
+    }
+
+    fn read_record(&mut self) -> Option<SpoolRecord> {
+        let record_type = self.read_slice(1)?[0];
+        let seq = self.read_u64()?;
+
+        match record_type {
+            MESSAGE_RECORD => {
+                let headers_count = self.read_u32()? as usize;
+                let mut headers = SbMessageHeaders::new();
+
+                for _ in 0..headers_count {
+                    let key = self.read_string()?;
+                    let value = self.read_string()?;
+                    headers = headers.add(key, value);
+                }
+
+                let content = self.read_bytes()?.to_vec();
+
+                Some(SpoolRecord::Message(
+                    seq,
+                    MessageToPublish::new_with_headers(content, headers),
+                ))
+            }
+            ACK_RECORD => Some(SpoolRecord::Ack(seq)),
+            _ => None,
+        }
+    }
+}
+
+fn serialize_message(record: &mut Vec<u8>, seq: u64, message: &MessageToPublish) {
+    record.push(MESSAGE_RECORD);
+    record.extend_from_slice(&seq.to_le_bytes());
+    record.extend_from_slice(&(message.headers.len() as u32).to_le_bytes());
+
+    for (key, value) in message.headers.iter() {
+        write_bytes(record, key.as_bytes());
+        write_bytes(record, value.as_bytes());
+    }
+
+    write_bytes(record, &message.content);
+}
+
+fn write_bytes(record: &mut Vec<u8>, value: &[u8]) {
+    record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    record.extend_from_slice(value);
+}
+
+fn get_segment_path(path: &PathBuf, segment_no: u64) -> PathBuf {
+    path.join(format!("{:020}.{}", segment_no, SEGMENT_FILE_EXTENSION))
+}
+
+fn open_segment(path: &PathBuf) -> std::io::Result<ActiveSegment> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok(ActiveSegment {
+        file: Arc::new(file),
+        size,
+    })
+}
+
+fn read_segment_paths(path: &PathBuf) -> std::io::Result<Vec<(u64, PathBuf)>> {
+    let mut result = Vec::new();
+
+    for entry in std::fs::read_dir(path)? {
+        let entry_path = entry?.path();
+
+        if entry_path.extension().and_then(|itm| itm.to_str()) != Some(SEGMENT_FILE_EXTENSION) {
+            continue;
+        }
+
+        let segment_no = entry_path
+            .file_stem()
+            .and_then(|itm| itm.to_str())
+            .and_then(|itm| itm.parse::<u64>().ok());
+
+        if let Some(segment_no) = segment_no {
+            result.push((segment_no, entry_path));
+        }
+    }
+
+    result.sort_by_key(|(segment_no, _)| *segment_no);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{publisher::MessageToPublish, SbMessageHeaders};
+
+    use super::{get_segment_path, open_segment, PublisherSpool, PublisherSpoolSettings};
+
+    fn get_test_settings(name: &str) -> PublisherSpoolSettings {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("my-sb-spool-{}-{}", name, std::process::id()));
+
+        let _ = std::fs::remove_dir_all(&path);
+
+        PublisherSpoolSettings::new(path)
+    }
+
+    fn get_segments_amount(settings: &PublisherSpoolSettings) -> usize {
+        std::fs::read_dir(settings.path.join("test-topic"))
+            .unwrap()
+            .count()
+    }
+
+    #[test]
+    fn test_not_published_messages_are_replayed() {
+        let settings = get_test_settings("replay");
+
+        {
+            let (mut spool, replayed) =
+                PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+            assert_eq!(0, replayed.len());
+
+            spool
+                .append(&[
+                    MessageToPublish::new(vec![1]),
+                    MessageToPublish::new_with_headers(
+                        vec![2],
+                        SbMessageHeaders::new().add("key", "value"),
+                    ),
+                    MessageToPublish::new(vec![3]),
+                ])
+                .unwrap();
+
+            spool.published(1).unwrap();
+        }
+
+        let (spool, replayed) = PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+
+        assert_eq!(2, replayed.len());
+        assert_eq!(vec![2], replayed[0].content);
+        assert_eq!(Some("value"), replayed[0].headers.get("key"));
+        assert_eq!(vec![3], replayed[1].content);
+        assert_eq!(2, spool.get_not_acked_amount());
+        assert_eq!(1, get_segments_amount(&settings));
+
+        let _ = std::fs::remove_dir_all(&settings.path);
+    }
+
+    #[test]
+    fn test_published_segments_are_deleted() {
+        let mut settings = get_test_settings("rotation");
+        settings.max_segment_size = 64;
+
+        let (mut spool, _) = PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+
+        for i in 0..10 {
+            spool.append(&[MessageToPublish::new(vec![i; 32])]).unwrap();
+        }
+
+        assert!(get_segments_amount(&settings) > 1);
+
+        spool.published(10).unwrap();
+
+        assert_eq!(1, get_segments_amount(&settings));
+        assert_eq!(0, spool.get_not_acked_amount());
+
+        let (_, replayed) = PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+        assert_eq!(0, replayed.len());
+
+        let _ = std::fs::remove_dir_all(&settings.path);
+    }
+
+    #[test]
+    fn test_cut_record_is_ignored() {
+        let settings = get_test_settings("cut-record");
+
+        {
+            let (mut spool, _) = PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+            spool
+                .append(&[MessageToPublish::new(vec![1, 2, 3])])
+                .unwrap();
+        }
+
+        let segment_path = std::fs::read_dir(settings.path.join("test-topic"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+
+        let mut content = std::fs::read(&segment_path).unwrap();
+        content.extend_from_slice(&[1, 5, 0]);
+        std::fs::write(&segment_path, content).unwrap();
+
+        let (_, replayed) = PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+        assert_eq!(1, replayed.len());
+
+        let _ = std::fs::remove_dir_all(&settings.path);
+    }
+
+    #[test]
+    fn test_crash_before_old_segments_are_deleted() {
+        let settings = get_test_settings("crash-on-open");
+
+        {
+            let (mut spool, _) = PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+            spool
+                .append(&[
+                    MessageToPublish::new(vec![1]),
+                    MessageToPublish::new(vec![2]),
+                ])
+                .unwrap();
+        }
+
+        // Like a crash after the messages are rewritten to the next segment
+        let segment_path = std::fs::read_dir(settings.path.join("test-topic"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+
+        std::fs::copy(
+            &segment_path,
+            get_segment_path(&settings.path.join("test-topic"), 100),
+        )
+        .unwrap();
+
+        let (spool, replayed) = PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+
+        assert_eq!(2, replayed.len());
+        assert_eq!(vec![1], replayed[0].content);
+        assert_eq!(vec![2], replayed[1].content);
+        assert_eq!(2, spool.get_not_acked_amount());
+
+        let _ = std::fs::remove_dir_all(&settings.path);
+    }
+
+    // Writes to /dev/full fail with "No space left on device"
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_failed_append_changes_nothing() {
+        let settings = get_test_settings("failed-append");
+
+        {
+            let (mut spool, _) = PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+            spool.append(&[MessageToPublish::new(vec![1])]).unwrap();
+
+            let segment = std::mem::replace(
+                &mut spool.active_segment,
+                open_segment(&PathBuf::from("/dev/full")).unwrap(),
+            );
+
+            let result = spool.append(&[
+                MessageToPublish::new(vec![2]),
+                MessageToPublish::new(vec![3]),
+            ]);
+
+            assert!(result.is_err());
+            assert_eq!(1, spool.get_not_acked_amount());
+
+            spool.active_segment = segment;
+            spool.append(&[MessageToPublish::new(vec![4])]).unwrap();
+        }
+
+        let (_, replayed) = PublisherSpool::open(settings.clone(), "test-topic").unwrap();
+
+        assert_eq!(2, replayed.len());
+        assert_eq!(vec![1], replayed[0].content);
+        assert_eq!(vec![4], replayed[1].content);
+
+        let _ = std::fs::remove_dir_all(&settings.path);
+    }
+}
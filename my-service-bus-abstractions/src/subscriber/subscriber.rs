@@ -82,6 +82,7 @@ impl<TMessageModel: MySbMessageDeserializer<Item = TMessageModel> + Send + Sync
         let mut can_not_serialize_messages = QueueWithIntervals::new();
 
         let mut deserialize_error = None;
+        let mut deserialization_failures = 0;
 
         for msg in messages_to_deliver {
             let content_result = TMessageModel::deserialize(&msg.content, &msg.headers);
@@ -117,10 +118,19 @@ impl<TMessageModel: MySbMessageDeserializer<Item = TMessageModel> + Send + Sync
                         ));
                     }
                     can_not_serialize_messages.enqueue(msg.id.get_value());
+                    deserialization_failures += 1;
                 }
             }
         }
 
+        if deserialization_failures > 0 {
+            self.data.client.deserialization_failed(
+                self.data.topic_id.as_str(),
+                self.data.queue_id.as_str(),
+                deserialization_failures,
+            );
+        }
+
         if messages.len() == 0 {
             self.data.client.confirm_delivery(
                 self.data.topic_id.as_str(),
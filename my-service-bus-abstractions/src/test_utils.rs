@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use rust_extensions::Logger;
+
+use crate::{
+    subscriber::MySbMessageDeserializer, MySbMessageSerializer, SbMessageHeaders, SubscriberError,
+};
+
+pub struct TestLogger;
+
+impl Logger for TestLogger {
+    fn write_info(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+    fn write_warning(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+    fn write_error(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+    fn write_fatal_error(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+    fn write_debug_info(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+}
+
+pub struct TestModel(pub Vec<u8>);
+
+impl MySbMessageDeserializer for TestModel {
+    type Item = TestModel;
+
+    fn deserialize(src: &[u8], _: &SbMessageHeaders) -> Result<Self::Item, SubscriberError> {
+        Ok(TestModel(src.to_vec()))
+    }
+}
+
+impl MySbMessageSerializer for TestModel {
+    fn serialize(
+        &self,
+        headers: Option<SbMessageHeaders>,
+    ) -> Result<(Vec<u8>, SbMessageHeaders), String> {
+        Ok((
+            self.0.clone(),
+            headers.unwrap_or_else(SbMessageHeaders::new),
+        ))
+    }
+}
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::sync::{broadcast, watch};
+
+const EVENTS_CHANNEL_CAPACITY: usize = 128;
+
+#[derive(Debug, Clone)]
+pub enum DisconnectReason {
+    ConnectionLost,
+    Rejected(String),
+    Shutdown,
+}
+
+#[derive(Debug, Clone)]
+pub enum MySbConnectionEvent {
+    Connecting {
+        endpoint: String,
+    },
+    Connected {
+        endpoint: String,
+        connection_id: i32,
+    },
+    GreetingAccepted {
+        connection_id: i32,
+    },
+    Disconnected {
+        connection_id: i32,
+        reason: DisconnectReason,
+    },
+    Rejected {
+        connection_id: i32,
+        reason: String,
+    },
+}
+
+struct ConnectionState {
+    greeting_accepted: bool,
+    reject_reason: Option<String>,
+    is_shutting_down: bool,
+}
+
+pub struct MySbConnectionEvents {
+    events: broadcast::Sender<MySbConnectionEvent>,
+    is_connected: watch::Sender<bool>,
+    state: Mutex<ConnectionState>,
+}
+
+impl MySbConnectionEvents {
+    pub fn new() -> Self {
+        let (events, _) = broadcast::channel(EVENTS_CHANNEL_CAPACITY);
+        let (is_connected, _) = watch::channel(false);
+
+        Self {
+            events,
+            is_connected,
+            state: Mutex::new(ConnectionState {
+                greeting_accepted: false,
+                reject_reason: None,
+                is_shutting_down: false,
+            }),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<MySbConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        *self.is_connected.borrow()
+    }
+
+    pub async fn wait_until_connected(&self, timeout: Duration) -> bool {
+        let mut receiver = self.is_connected.subscribe();
+        let result = tokio::time::timeout(timeout, receiver.wait_for(|value| *value)).await;
+
+        match result {
+            Ok(result) => result.is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn connecting(&self, endpoint: &str) {
+        self.publish(MySbConnectionEvent::Connecting {
+            endpoint: endpoint.to_string(),
+        });
+    }
+
+    pub fn connected(&self, endpoint: String, connection_id: i32) {
+        {
+            let mut write_access = self.state.lock();
+            write_access.greeting_accepted = false;
+            write_access.reject_reason = None;
+        }
+
+        self.is_connected.send_replace(true);
+        self.publish(MySbConnectionEvent::Connected {
+            endpoint,
+            connection_id,
+        });
+    }
+
+    // The server answers a wrong greeting with a Reject packet,
+    // so any other packet means the greeting is accepted.
+    // On an idle connection it is the Pong to the first Ping.
+    pub fn packet_received(&self, connection_id: i32) {
+        {
+            let mut write_access = self.state.lock();
+
+            if write_access.greeting_accepted || write_access.reject_reason.is_some() {
+                return;
+            }
+
+            write_access.greeting_accepted = true;
+        }
+
+        self.publish(MySbConnectionEvent::GreetingAccepted { connection_id });
+    }
+
+    pub fn rejected(&self, connection_id: i32, reason: String) {
+        {
+            let mut write_access = self.state.lock();
+            write_access.reject_reason = Some(reason.clone());
+        }
+
+        self.publish(MySbConnectionEvent::Rejected {
+            connection_id,
+            reason,
+        });
+    }
+
+    pub fn shutting_down(&self) {
+        let mut write_access = self.state.lock();
+        write_access.is_shutting_down = true;
+    }
+
+    pub fn disconnected(&self, connection_id: i32) {
+        let reason = {
+            let mut write_access = self.state.lock();
+
+            if let Some(reject_reason) = write_access.reject_reason.take() {
+                DisconnectReason::Rejected(reject_reason)
+            } else if write_access.is_shutting_down {
+                DisconnectReason::Shutdown
+            } else {
+                DisconnectReason::ConnectionLost
+            }
+        };
+
+        self.is_connected.send_replace(false);
+        self.publish(MySbConnectionEvent::Disconnected {
+            connection_id,
+            reason,
+        });
+    }
+
+    fn publish(&self, event: MySbConnectionEvent) {
+        // There are no receivers if nobody is subscribed to the events
+        let _ = self.events.send(event);
+    }
+}
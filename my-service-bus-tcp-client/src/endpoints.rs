@@ -0,0 +1,153 @@
+use parking_lot::Mutex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverPolicy {
+    // Each reconnect starts from the first endpoint; the next ones are tried only if it fails
+    Priority,
+    // Each reconnect continues with the endpoint which goes after the one which was lost
+    RoundRobin,
+}
+
+struct MySbEndpointsInner {
+    index: usize,
+    attempt_in_progress: bool,
+    policy: FailoverPolicy,
+    last_endpoint: Option<String>,
+    connected_endpoint: Option<String>,
+}
+
+pub struct MySbEndpoints {
+    inner: Mutex<MySbEndpointsInner>,
+}
+
+impl MySbEndpoints {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(MySbEndpointsInner {
+                index: 0,
+                attempt_in_progress: false,
+                policy: FailoverPolicy::Priority,
+                last_endpoint: None,
+                connected_endpoint: None,
+            }),
+        }
+    }
+
+    pub fn get_endpoint_to_connect(
+        &self,
+        endpoints: &[String],
+        policy: FailoverPolicy,
+    ) -> Option<String> {
+        if endpoints.len() == 0 {
+            return None;
+        }
+
+        let mut write_access = self.inner.lock();
+
+        write_access.policy = policy;
+
+        if write_access.attempt_in_progress {
+            write_access.index += 1;
+        }
+
+        if write_access.index >= endpoints.len() {
+            write_access.index = 0;
+        }
+
+        write_access.attempt_in_progress = true;
+
+        let result = endpoints[write_access.index].to_string();
+        write_access.last_endpoint = Some(result.clone());
+        Some(result)
+    }
+
+    pub fn connected(&self) {
+        let mut write_access = self.inner.lock();
+        write_access.attempt_in_progress = false;
+        write_access.connected_endpoint = write_access.last_endpoint.clone();
+    }
+
+    pub fn disconnected(&self) {
+        let mut write_access = self.inner.lock();
+        write_access.connected_endpoint = None;
+
+        match write_access.policy {
+            FailoverPolicy::Priority => {
+                write_access.index = 0;
+            }
+            FailoverPolicy::RoundRobin => {
+                write_access.index += 1;
+            }
+        }
+    }
+
+    pub fn get_connected_endpoint(&self) -> Option<String> {
+        let read_access = self.inner.lock();
+        read_access.connected_endpoint.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_endpoints() -> Vec<String> {
+        vec!["primary:6421".to_string(), "standby:6421".to_string()]
+    }
+
+    #[test]
+    fn test_failed_attempt_switches_to_next_endpoint() {
+        let endpoints = MySbEndpoints::new();
+        let list = get_endpoints();
+
+        let result = endpoints.get_endpoint_to_connect(&list, FailoverPolicy::Priority);
+        assert_eq!("primary:6421", result.unwrap());
+
+        let result = endpoints.get_endpoint_to_connect(&list, FailoverPolicy::Priority);
+        assert_eq!("standby:6421", result.unwrap());
+
+        let result = endpoints.get_endpoint_to_connect(&list, FailoverPolicy::Priority);
+        assert_eq!("primary:6421", result.unwrap());
+    }
+
+    #[test]
+    fn test_priority_policy_goes_back_to_primary_after_disconnect() {
+        let endpoints = MySbEndpoints::new();
+        let list = get_endpoints();
+
+        endpoints.get_endpoint_to_connect(&list, FailoverPolicy::Priority);
+        endpoints.get_endpoint_to_connect(&list, FailoverPolicy::Priority);
+        endpoints.connected();
+
+        assert_eq!("standby:6421", endpoints.get_connected_endpoint().unwrap());
+
+        endpoints.disconnected();
+        assert!(endpoints.get_connected_endpoint().is_none());
+
+        let result = endpoints.get_endpoint_to_connect(&list, FailoverPolicy::Priority);
+        assert_eq!("primary:6421", result.unwrap());
+    }
+
+    #[test]
+    fn test_round_robin_policy_goes_to_next_endpoint_after_disconnect() {
+        let endpoints = MySbEndpoints::new();
+        let list = get_endpoints();
+
+        endpoints.get_endpoint_to_connect(&list, FailoverPolicy::RoundRobin);
+        endpoints.connected();
+
+        assert_eq!("primary:6421", endpoints.get_connected_endpoint().unwrap());
+
+        endpoints.disconnected();
+
+        let result = endpoints.get_endpoint_to_connect(&list, FailoverPolicy::RoundRobin);
+        assert_eq!("standby:6421", result.unwrap());
+    }
+
+    #[test]
+    fn test_no_endpoints() {
+        let endpoints = MySbEndpoints::new();
+        let result = endpoints.get_endpoint_to_connect(&[], FailoverPolicy::Priority);
+        assert!(result.is_none());
+    }
+}
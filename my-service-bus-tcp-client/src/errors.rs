@@ -0,0 +1,36 @@
+use my_service_bus_abstractions::PublishError;
+
+#[derive(Debug)]
+pub enum ShutdownError {
+    Timeout {
+        deliveries_in_flight: usize,
+        messages_in_internal_queues: usize,
+        publish_requests_in_flight: usize,
+    },
+}
+
+#[derive(Debug)]
+pub enum SubscribeError {
+    Timeout { topic_id: String, queue_id: String },
+    AlreadyRegistered { topic_id: String, queue_id: String },
+    Unsubscribed { topic_id: String, queue_id: String },
+}
+
+#[derive(Debug)]
+pub enum UnsubscribeError {
+    NotSubscribed { topic_id: String, queue_id: String },
+    Timeout { deliveries_in_flight: usize },
+}
+
+#[derive(Debug)]
+pub enum IgnoreMessagesError {
+    InvalidRule { line: String, reason: String },
+    CanNotReadFile { path: String, err: String },
+}
+
+#[derive(Debug)]
+pub enum RpcError {
+    Timeout,
+    Disconnected,
+    PublishError(PublishError),
+}
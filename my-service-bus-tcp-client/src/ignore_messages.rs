@@ -0,0 +1,265 @@
+use std::{collections::HashMap, path::Path};
+
+use my_service_bus_abstractions::{queue_with_intervals::QueueIndexRange, MySbMessage};
+use parking_lot::Mutex;
+
+use crate::IgnoreMessagesError;
+
+pub const IGNORE_MESSAGE_ENV_NAME: &'static str = "SB_IGNORE_MESSAGE";
+
+#[derive(Debug, Clone)]
+pub struct IgnoreMessagesRule {
+    pub topic_id: String,
+    pub queue_id: String,
+    pub ranges: Vec<QueueIndexRange<i64>>,
+}
+
+impl IgnoreMessagesRule {
+    pub fn new(
+        topic_id: impl Into<String>,
+        queue_id: impl Into<String>,
+        ranges: Vec<QueueIndexRange<i64>>,
+    ) -> Self {
+        Self {
+            topic_id: topic_id.into(),
+            queue_id: queue_id.into(),
+            ranges,
+        }
+    }
+
+    // TOPIC_ID=xxx;QUEUE_ID=xxx;MESSAGE_ID=5,10-20
+    pub fn parse(line: &str) -> Result<Self, IgnoreMessagesError> {
+        let mut topic_id = None;
+        let mut queue_id = None;
+        let mut ranges = None;
+
+        for key_value in line.trim().split(";") {
+            let mut key_value = key_value.splitn(2, "=");
+            let key = key_value.next().unwrap_or_default().trim();
+
+            let value = match key_value.next() {
+                Some(value) => value.trim(),
+                None => return Err(invalid_line(line, "key=value is expected")),
+            };
+
+            match key {
+                "TOPIC_ID" => topic_id = Some(value.to_string()),
+                "QUEUE_ID" => queue_id = Some(value.to_string()),
+                "MESSAGE_ID" => {
+                    ranges = Some(parse_ids(value).map_err(|err| invalid_line(line, err))?)
+                }
+                _ => return Err(invalid_line(line, format!("unknown key {}", key))),
+            }
+        }
+
+        let topic_id = topic_id.ok_or_else(|| invalid_line(line, "TOPIC_ID is missing"))?;
+        let queue_id = queue_id.ok_or_else(|| invalid_line(line, "QUEUE_ID is missing"))?;
+        let ranges = ranges.ok_or_else(|| invalid_line(line, "MESSAGE_ID is missing"))?;
+
+        Ok(Self::new(topic_id, queue_id, ranges))
+    }
+
+    pub fn from_env() -> Result<Option<Self>, IgnoreMessagesError> {
+        match std::env::var(IGNORE_MESSAGE_ENV_NAME) {
+            Ok(line) => Ok(Some(Self::parse(line.as_str())?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    // One rule per line. Empty lines and lines started with # are skipped.
+    pub fn load_from_file(path: impl AsRef<Path>) -> Result<Vec<Self>, IgnoreMessagesError> {
+        let content = std::fs::read_to_string(path.as_ref()).map_err(|err| {
+            IgnoreMessagesError::CanNotReadFile {
+                path: path.as_ref().display().to_string(),
+                err: err.to_string(),
+            }
+        })?;
+
+        let mut result = Vec::new();
+
+        for line in content.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with("#") {
+                continue;
+            }
+
+            result.push(Self::parse(line)?);
+        }
+
+        Ok(result)
+    }
+}
+
+fn invalid_line(line: &str, reason: impl Into<String>) -> IgnoreMessagesError {
+    IgnoreMessagesError::InvalidRule {
+        line: line.to_string(),
+        reason: reason.into(),
+    }
+}
+
+// 5,10-20
+pub fn parse_ids(value: &str) -> Result<Vec<QueueIndexRange<i64>>, String> {
+    let mut result = Vec::new();
+
+    for item in value.split(",") {
+        let item = item.trim();
+
+        let (from_id, to_id) = match item.split_once("-") {
+            Some((from_id, to_id)) => (from_id.trim(), to_id.trim()),
+            None => (item, item),
+        };
+
+        let from_id: i64 = from_id
+            .parse()
+            .map_err(|_| format!("invalid message id {}", item))?;
+        let to_id: i64 = to_id
+            .parse()
+            .map_err(|_| format!("invalid message id {}", item))?;
+
+        if from_id > to_id {
+            return Err(format!("invalid message id range {}", item));
+        }
+
+        result.push(QueueIndexRange { from_id, to_id });
+    }
+
+    Ok(result)
+}
+
+pub struct IgnoredMessages {
+    rules: Mutex<HashMap<(String, String), Vec<QueueIndexRange<i64>>>>,
+}
+
+impl IgnoredMessages {
+    pub fn new() -> Self {
+        Self {
+            rules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn add(&self, rule: IgnoreMessagesRule) {
+        let mut write_access = self.rules.lock();
+        write_access
+            .entry((rule.topic_id, rule.queue_id))
+            .or_insert_with(Vec::new)
+            .extend(rule.ranges);
+    }
+
+    pub fn remove(&self, topic_id: &str, queue_id: &str) -> bool {
+        let mut write_access = self.rules.lock();
+        write_access
+            .remove(&(topic_id.to_string(), queue_id.to_string()))
+            .is_some()
+    }
+
+    pub fn get_rules(&self) -> Vec<IgnoreMessagesRule> {
+        let read_access = self.rules.lock();
+        read_access
+            .iter()
+            .map(|((topic_id, queue_id), ranges)| {
+                IgnoreMessagesRule::new(topic_id.as_str(), queue_id.as_str(), ranges.clone())
+            })
+            .collect()
+    }
+
+    // Removes ignored messages from the batch and returns their ids
+    pub fn filter(
+        &self,
+        topic_id: &str,
+        queue_id: &str,
+        messages: &mut Vec<MySbMessage>,
+    ) -> Vec<i64> {
+        let read_access = self.rules.lock();
+
+        let ranges = match read_access.get(&(topic_id.to_string(), queue_id.to_string())) {
+            Some(ranges) => ranges,
+            None => return Vec::new(),
+        };
+
+        let mut ignored = Vec::new();
+
+        messages.retain(|itm| {
+            let id = itm.id.get_value();
+
+            if ranges
+                .iter()
+                .any(|range| range.from_id <= id && id <= range.to_id)
+            {
+                ignored.push(id);
+                return false;
+            }
+
+            true
+        });
+
+        ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use my_service_bus_abstractions::{MySbMessage, SbMessageHeaders};
+
+    use super::{IgnoreMessagesRule, IgnoredMessages};
+    use crate::IgnoreMessagesError;
+
+    fn message(id: i64) -> MySbMessage {
+        MySbMessage {
+            id: id.into(),
+            attempt_no: 0,
+            headers: SbMessageHeaders::new(),
+            content: vec![],
+        }
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let rule =
+            IgnoreMessagesRule::parse("TOPIC_ID=topic;QUEUE_ID=queue;MESSAGE_ID=5,10-20").unwrap();
+
+        assert_eq!("topic", rule.topic_id);
+        assert_eq!("queue", rule.queue_id);
+        assert_eq!(2, rule.ranges.len());
+        assert_eq!((5, 5), (rule.ranges[0].from_id, rule.ranges[0].to_id));
+        assert_eq!((10, 20), (rule.ranges[1].from_id, rule.ranges[1].to_id));
+    }
+
+    #[test]
+    fn test_invalid_rules_are_errors() {
+        for line in [
+            "TOPIC_ID=topic;QUEUE_ID=queue",
+            "TOPIC_ID=topic;QUEUE_ID=queue;MESSAGE_ID=abc",
+            "TOPIC_ID=topic;QUEUE_ID=queue;MESSAGE_ID=20-10",
+            "TOPIC_ID=topic;QUEUE;MESSAGE_ID=1",
+            "TOPIC=topic;QUEUE_ID=queue;MESSAGE_ID=1",
+        ] {
+            match IgnoreMessagesRule::parse(line) {
+                Err(IgnoreMessagesError::InvalidRule { .. }) => {}
+                other => panic!("{} must be invalid. Got: {:?}", line, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let ignored_messages = IgnoredMessages::new();
+        ignored_messages.add(
+            IgnoreMessagesRule::parse("TOPIC_ID=topic;QUEUE_ID=queue;MESSAGE_ID=2-3").unwrap(),
+        );
+
+        let mut messages = vec![message(1), message(2), message(3), message(4)];
+
+        assert!(ignored_messages
+            .filter("topic", "other-queue", &mut messages)
+            .is_empty());
+
+        let ignored = ignored_messages.filter("topic", "queue", &mut messages);
+
+        assert_eq!(vec![2, 3], ignored);
+        assert_eq!(2, messages.len());
+
+        assert!(ignored_messages.remove("topic", "queue"));
+        assert!(ignored_messages.get_rules().is_empty());
+    }
+}
@@ -7,6 +7,30 @@ mod tcp_client_data;
 pub use settings::MyServiceBusSettings;
 
 pub use my_sb_client::MyServiceBusClient;
+mod my_sb_client_builder;
+pub use my_sb_client_builder::*;
+pub use subscribers::MySbSubscription;
 use tcp_client_data::*;
-mod ignore_message;
-pub use ignore_message::*;
+mod ignore_messages;
+pub use ignore_messages::*;
+mod errors;
+pub use errors::*;
+mod endpoints;
+pub use endpoints::*;
+mod tls_settings;
+pub use tls_settings::*;
+mod connection_events;
+pub use connection_events::*;
+mod rejects;
+pub use rejects::*;
+mod publish_retry_policy;
+pub use publish_retry_policy::*;
+mod metrics;
+pub use metrics::*;
+mod publish_in_flight_limits;
+pub use publish_in_flight_limits::*;
+mod unexpected_delivery;
+pub use unexpected_delivery::*;
+pub mod rpc;
+#[cfg(test)]
+mod test_utils;
@@ -0,0 +1,397 @@
+use std::collections::BTreeMap;
+
+use my_service_bus_abstractions::publisher::{InternalQueueMetrics, BATCH_MESSAGES_BUCKETS};
+use parking_lot::Mutex;
+
+#[derive(Debug, Clone, Default)]
+pub struct MySbPublishMetrics {
+    pub sent: u64,
+    pub confirmed: u64,
+    pub failed: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MySbQueueMetrics {
+    pub batches_delivered: u64,
+    pub messages_delivered: u64,
+    pub messages_redelivered: u64,
+    pub deserialization_failures: u64,
+    pub confirmations_all_ok: u64,
+    pub confirmations_all_fail: u64,
+    pub confirmations_partial: u64,
+    pub confirmations_intermediary: u64,
+    pub unexpected_deliveries: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MySbMetricsSnapshot {
+    pub publishes: BTreeMap<String, MySbPublishMetrics>,
+    pub publish_requests_in_flight: usize,
+    pub queues: BTreeMap<(String, String), MySbQueueMetrics>,
+    pub internal_queues: BTreeMap<String, InternalQueueMetrics>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ConfirmationType {
+    AllOk,
+    AllFail,
+    Partial,
+    Intermediary,
+}
+
+pub struct MySbMetrics {
+    publishes: Mutex<BTreeMap<String, MySbPublishMetrics>>,
+    queues: Mutex<BTreeMap<(String, String), MySbQueueMetrics>>,
+}
+
+impl MySbMetrics {
+    pub fn new() -> Self {
+        Self {
+            publishes: Mutex::new(BTreeMap::new()),
+            queues: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn update_publish(&self, topic_id: &str, update: impl FnOnce(&mut MySbPublishMetrics)) {
+        let mut write_access = self.publishes.lock();
+        let metrics = write_access.entry(topic_id.to_string()).or_default();
+
+        update(metrics);
+    }
+
+    fn update_queue(
+        &self,
+        topic_id: &str,
+        queue_id: &str,
+        update: impl FnOnce(&mut MySbQueueMetrics),
+    ) {
+        let mut write_access = self.queues.lock();
+        let metrics = write_access
+            .entry((topic_id.to_string(), queue_id.to_string()))
+            .or_default();
+
+        update(metrics);
+    }
+
+    pub fn publish_sent(&self, topic_id: &str) {
+        self.update_publish(topic_id, |metrics| metrics.sent += 1);
+    }
+
+    pub fn publish_confirmed(&self, topic_id: &str) {
+        self.update_publish(topic_id, |metrics| metrics.confirmed += 1);
+    }
+
+    pub fn publish_failed(&self, topic_id: &str) {
+        self.update_publish(topic_id, |metrics| metrics.failed += 1);
+    }
+
+    pub fn batch_delivered(&self, topic_id: &str, queue_id: &str, messages: u64, redelivered: u64) {
+        self.update_queue(topic_id, queue_id, |metrics| {
+            metrics.batches_delivered += 1;
+            metrics.messages_delivered += messages;
+            metrics.messages_redelivered += redelivered;
+        });
+    }
+
+    pub fn deserialization_failed(&self, topic_id: &str, queue_id: &str, messages: u64) {
+        self.update_queue(topic_id, queue_id, |metrics| {
+            metrics.deserialization_failures += messages
+        });
+    }
+
+    // NewMessages for a queue without a subscriber
+    pub fn unexpected_delivery(&self, topic_id: &str, queue_id: &str) {
+        self.update_queue(topic_id, queue_id, |metrics| {
+            metrics.unexpected_deliveries += 1
+        });
+    }
+
+    pub fn confirmation_sent(
+        &self,
+        topic_id: &str,
+        queue_id: &str,
+        confirmation: ConfirmationType,
+    ) {
+        self.update_queue(topic_id, queue_id, |metrics| match confirmation {
+            ConfirmationType::AllOk => metrics.confirmations_all_ok += 1,
+            ConfirmationType::AllFail => metrics.confirmations_all_fail += 1,
+            ConfirmationType::Partial => metrics.confirmations_partial += 1,
+            ConfirmationType::Intermediary => metrics.confirmations_intermediary += 1,
+        });
+    }
+
+    pub fn get_snapshot(
+        &self,
+        publish_requests_in_flight: usize,
+        internal_queues: BTreeMap<String, InternalQueueMetrics>,
+    ) -> MySbMetricsSnapshot {
+        MySbMetricsSnapshot {
+            publishes: self.publishes.lock().clone(),
+            publish_requests_in_flight,
+            queues: self.queues.lock().clone(),
+            internal_queues,
+        }
+    }
+}
+
+impl MySbMetricsSnapshot {
+    pub fn to_prometheus(&self) -> String {
+        let mut result = String::new();
+
+        write_publish_metric(&mut result, self, "my_sb_publish_sent_total", |m| m.sent);
+        write_publish_metric(&mut result, self, "my_sb_publish_confirmed_total", |m| {
+            m.confirmed
+        });
+        write_publish_metric(&mut result, self, "my_sb_publish_failed_total", |m| {
+            m.failed
+        });
+
+        result.push_str("# TYPE my_sb_publish_requests_in_flight gauge\n");
+        result.push_str(&format!(
+            "my_sb_publish_requests_in_flight {}\n",
+            self.publish_requests_in_flight
+        ));
+
+        write_queue_metric(&mut result, self, "my_sb_batches_delivered_total", |m| {
+            m.batches_delivered
+        });
+        write_queue_metric(&mut result, self, "my_sb_messages_delivered_total", |m| {
+            m.messages_delivered
+        });
+        write_queue_metric(&mut result, self, "my_sb_messages_redelivered_total", |m| {
+            m.messages_redelivered
+        });
+        write_queue_metric(
+            &mut result,
+            self,
+            "my_sb_deserialization_failures_total",
+            |m| m.deserialization_failures,
+        );
+        write_queue_metric(
+            &mut result,
+            self,
+            "my_sb_unexpected_deliveries_total",
+            |m| m.unexpected_deliveries,
+        );
+
+        result.push_str("# TYPE my_sb_confirmations_sent_total counter\n");
+        for ((topic_id, queue_id), metrics) in &self.queues {
+            for (confirmation_type, value) in [
+                ("all_ok", metrics.confirmations_all_ok),
+                ("all_fail", metrics.confirmations_all_fail),
+                ("partial", metrics.confirmations_partial),
+                ("intermediary", metrics.confirmations_intermediary),
+            ] {
+                result.push_str(&format!(
+                    "my_sb_confirmations_sent_total{{topic_id=\"{}\",queue_id=\"{}\",type=\"{}\"}} {}\n",
+                    escape_label_value(topic_id),
+                    escape_label_value(queue_id),
+                    confirmation_type,
+                    value
+                ));
+            }
+        }
+
+        write_internal_queue_metrics(&mut result, self);
+
+        result
+    }
+}
+
+fn write_internal_queue_metrics(result: &mut String, snapshot: &MySbMetricsSnapshot) {
+    result.push_str("# TYPE my_sb_internal_queue_size gauge\n");
+    for (topic_id, metrics) in &snapshot.internal_queues {
+        result.push_str(&format!(
+            "my_sb_internal_queue_size{{topic_id=\"{}\"}} {}\n",
+            escape_label_value(topic_id),
+            metrics.queue_size
+        ));
+    }
+
+    result.push_str("# TYPE my_sb_internal_queue_dropped_messages_total counter\n");
+    for (topic_id, metrics) in &snapshot.internal_queues {
+        result.push_str(&format!(
+            "my_sb_internal_queue_dropped_messages_total{{topic_id=\"{}\"}} {}\n",
+            escape_label_value(topic_id),
+            metrics.dropped_messages
+        ));
+    }
+
+    result.push_str("# TYPE my_sb_internal_queue_batch_messages histogram\n");
+    for (topic_id, metrics) in &snapshot.internal_queues {
+        let topic_id = escape_label_value(topic_id);
+        let mut batches = 0;
+
+        for (bucket, value) in BATCH_MESSAGES_BUCKETS
+            .iter()
+            .zip(metrics.batch_messages_buckets.iter())
+        {
+            batches += value;
+            result.push_str(&format!(
+                "my_sb_internal_queue_batch_messages_bucket{{topic_id=\"{}\",le=\"{}\"}} {}\n",
+                topic_id, bucket, batches
+            ));
+        }
+
+        result.push_str(&format!(
+            "my_sb_internal_queue_batch_messages_bucket{{topic_id=\"{}\",le=\"+Inf\"}} {}\n",
+            topic_id, metrics.batches
+        ));
+        result.push_str(&format!(
+            "my_sb_internal_queue_batch_messages_sum{{topic_id=\"{}\"}} {}\n",
+            topic_id, metrics.batch_messages_sum
+        ));
+        result.push_str(&format!(
+            "my_sb_internal_queue_batch_messages_count{{topic_id=\"{}\"}} {}\n",
+            topic_id, metrics.batches
+        ));
+    }
+
+    result.push_str("# TYPE my_sb_internal_queue_batch_bytes_total counter\n");
+    for (topic_id, metrics) in &snapshot.internal_queues {
+        result.push_str(&format!(
+            "my_sb_internal_queue_batch_bytes_total{{topic_id=\"{}\"}} {}\n",
+            escape_label_value(topic_id),
+            metrics.batch_bytes_sum
+        ));
+    }
+}
+
+fn write_publish_metric(
+    result: &mut String,
+    snapshot: &MySbMetricsSnapshot,
+    name: &str,
+    get_value: impl Fn(&MySbPublishMetrics) -> u64,
+) {
+    result.push_str(&format!("# TYPE {} counter\n", name));
+
+    for (topic_id, metrics) in &snapshot.publishes {
+        result.push_str(&format!(
+            "{}{{topic_id=\"{}\"}} {}\n",
+            name,
+            escape_label_value(topic_id),
+            get_value(metrics)
+        ));
+    }
+}
+
+fn write_queue_metric(
+    result: &mut String,
+    snapshot: &MySbMetricsSnapshot,
+    name: &str,
+    get_value: impl Fn(&MySbQueueMetrics) -> u64,
+) {
+    result.push_str(&format!("# TYPE {} counter\n", name));
+
+    for ((topic_id, queue_id), metrics) in &snapshot.queues {
+        result.push_str(&format!(
+            "{}{{topic_id=\"{}\",queue_id=\"{}\"}} {}\n",
+            name,
+            escape_label_value(topic_id),
+            escape_label_value(queue_id),
+            get_value(metrics)
+        ));
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use my_service_bus_abstractions::publisher::InternalQueueMetrics;
+
+    use super::{ConfirmationType, MySbMetrics};
+
+    #[test]
+    fn test_prometheus_rendering() {
+        let metrics = MySbMetrics::new();
+
+        metrics.publish_sent("topic");
+        metrics.publish_sent("topic");
+        metrics.publish_confirmed("topic");
+        metrics.publish_failed("topic");
+
+        metrics.batch_delivered("topic", "queue", 3, 1);
+        metrics.deserialization_failed("topic", "queue", 2);
+        metrics.confirmation_sent("topic", "queue", ConfirmationType::AllOk);
+        metrics.confirmation_sent("topic", "queue", ConfirmationType::Partial);
+        metrics.unexpected_delivery("topic", "queue");
+
+        let result = metrics.get_snapshot(5, BTreeMap::new()).to_prometheus();
+
+        assert!(result.contains("# TYPE my_sb_publish_sent_total counter\n"));
+        assert!(result.contains("my_sb_publish_sent_total{topic_id=\"topic\"} 2\n"));
+        assert!(result.contains("my_sb_publish_confirmed_total{topic_id=\"topic\"} 1\n"));
+        assert!(result.contains("my_sb_publish_failed_total{topic_id=\"topic\"} 1\n"));
+        assert!(result.contains("my_sb_publish_requests_in_flight 5\n"));
+        assert!(result
+            .contains("my_sb_messages_delivered_total{topic_id=\"topic\",queue_id=\"queue\"} 3\n"));
+        assert!(result.contains(
+            "my_sb_messages_redelivered_total{topic_id=\"topic\",queue_id=\"queue\"} 1\n"
+        ));
+        assert!(result.contains(
+            "my_sb_deserialization_failures_total{topic_id=\"topic\",queue_id=\"queue\"} 2\n"
+        ));
+        assert!(result.contains(
+            "my_sb_unexpected_deliveries_total{topic_id=\"topic\",queue_id=\"queue\"} 1\n"
+        ));
+        assert!(result.contains(
+            "my_sb_confirmations_sent_total{topic_id=\"topic\",queue_id=\"queue\",type=\"partial\"} 1\n"
+        ));
+        assert!(result.contains(
+            "my_sb_confirmations_sent_total{topic_id=\"topic\",queue_id=\"queue\",type=\"all_fail\"} 0\n"
+        ));
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        let metrics = MySbMetrics::new();
+        metrics.publish_sent("to\"pic");
+
+        let result = metrics.get_snapshot(0, BTreeMap::new()).to_prometheus();
+
+        assert!(result.contains("my_sb_publish_sent_total{topic_id=\"to\\\"pic\"} 1\n"));
+    }
+
+    #[test]
+    fn test_internal_queue_histogram() {
+        let mut internal_queue = InternalQueueMetrics::default();
+        internal_queue.batch_is_formed(1, 10);
+        internal_queue.batch_is_formed(3, 30);
+        internal_queue.batch_is_formed(100_000, 1_000);
+
+        let mut internal_queues = BTreeMap::new();
+        internal_queues.insert("topic".to_string(), internal_queue);
+
+        let result = MySbMetrics::new()
+            .get_snapshot(0, internal_queues)
+            .to_prometheus();
+
+        assert!(result.contains("# TYPE my_sb_internal_queue_batch_messages histogram\n"));
+        assert!(result.contains(
+            "my_sb_internal_queue_batch_messages_bucket{topic_id=\"topic\",le=\"1\"} 1\n"
+        ));
+        assert!(result.contains(
+            "my_sb_internal_queue_batch_messages_bucket{topic_id=\"topic\",le=\"5\"} 2\n"
+        ));
+        assert!(result.contains(
+            "my_sb_internal_queue_batch_messages_bucket{topic_id=\"topic\",le=\"50000\"} 2\n"
+        ));
+        assert!(result.contains(
+            "my_sb_internal_queue_batch_messages_bucket{topic_id=\"topic\",le=\"+Inf\"} 3\n"
+        ));
+        assert!(
+            result.contains("my_sb_internal_queue_batch_messages_sum{topic_id=\"topic\"} 100004\n")
+        );
+        assert!(
+            result.contains("my_sb_internal_queue_batch_messages_count{topic_id=\"topic\"} 3\n")
+        );
+    }
+}
@@ -1,11 +1,21 @@
-use std::sync::atomic::AtomicBool;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::Instant;
 
 use crate::publishers::MySbPublishers;
+use crate::rpc::{RpcClient, RpcHandler, RpcPendingCalls, RpcServer};
 use crate::subscribers::MySbSubscribers;
 
-use crate::{IgnoreMessage, TcpClientData};
+use crate::{
+    IgnoreMessagesError, IgnoreMessagesRule, IgnoredMessages, MySbConnectionEvent,
+    MySbConnectionEvents, MySbEndpoints, MySbMetrics, MySbMetricsSnapshot, MySbRejects,
+    MySbSubscription, MyServiceBusClientBuilder, ShutdownError, SubscribeError, TcpClientData,
+    UnsubscribeError, IGNORE_MESSAGE_ENV_NAME,
+};
 use my_service_bus_abstractions::publisher::{MyServiceBusPublisher, PublisherWithInternalQueue};
+use my_service_bus_abstractions::queue_with_intervals::QueueIndexRange;
 use my_service_bus_abstractions::subscriber::MySbMessageDeserializer;
 use my_service_bus_abstractions::subscriber::Subscriber;
 use my_service_bus_abstractions::subscriber::SubscriberCallback;
@@ -19,24 +29,53 @@ use super::MyServiceBusSettings;
 
 const TCP_CLIENT_NAME: &str = "MySbTcpClient";
 
+const ENV_INFO_ENV_NAME: &str = "ENV_INFO";
+
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 struct TcpConnectionSettings {
     my_sb_settings: Arc<dyn MyServiceBusSettings + Send + Sync + 'static>,
+    endpoints: Arc<MySbEndpoints>,
+    connection_events: Arc<MySbConnectionEvents>,
+    rejects: Arc<MySbRejects>,
 }
 
 impl TcpConnectionSettings {
-    pub fn new(my_sb_settings: Arc<dyn MyServiceBusSettings + Send + Sync + 'static>) -> Self {
-        Self { my_sb_settings }
+    pub fn new(
+        my_sb_settings: Arc<dyn MyServiceBusSettings + Send + Sync + 'static>,
+        endpoints: Arc<MySbEndpoints>,
+        connection_events: Arc<MySbConnectionEvents>,
+        rejects: Arc<MySbRejects>,
+    ) -> Self {
+        Self {
+            my_sb_settings,
+            endpoints,
+            connection_events,
+            rejects,
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl TcpClientSocketSettings for TcpConnectionSettings {
     async fn get_host_port(&self) -> Option<String> {
-        self.my_sb_settings.get_host_port().await.into()
+        if !self.rejects.wait_before_reconnect().await {
+            return None;
+        }
+
+        let host_ports = self.my_sb_settings.get_host_ports().await;
+        let endpoint = self
+            .endpoints
+            .get_endpoint_to_connect(&host_ports, self.my_sb_settings.get_failover_policy())?;
+
+        self.connection_events.connecting(endpoint.as_str());
+
+        Some(endpoint)
     }
 
     async fn get_tls_settings(&self) -> Option<TlsSettings> {
-        None
+        let tls_settings = self.my_sb_settings.get_tls_settings().await?;
+        Some(tls_settings.into())
     }
 }
 
@@ -46,29 +85,88 @@ pub struct MyServiceBusClient {
 }
 
 impl MyServiceBusClient {
+    // Reads ENV_INFO and SB_IGNORE_MESSAGE from the process environment.
+    // Use MyServiceBusClientBuilder for explicit configuration.
     pub fn new(
         app_name: impl Into<StrOrString<'static>>,
         app_version: impl Into<StrOrString<'static>>,
         settings: Arc<dyn MyServiceBusSettings + Send + Sync + 'static>,
         logger: Arc<dyn Logger + Send + Sync + 'static>,
     ) -> Self {
-        let tcp_settings = TcpConnectionSettings::new(settings);
+        let mut builder = MyServiceBusClientBuilder::new(app_name, app_version, settings, logger);
+
+        if let Ok(env_info) = std::env::var(ENV_INFO_ENV_NAME) {
+            builder = builder.with_env_info(env_info);
+        }
+
+        match IgnoreMessagesRule::from_env() {
+            Ok(Some(rule)) => builder = builder.with_ignored_messages(rule),
+            Ok(None) => {}
+            Err(err) => {
+                builder.logger.write_error(
+                    "MyServiceBusClient::new".to_string(),
+                    format!("{} is skipped. Err: {:?}", IGNORE_MESSAGE_ENV_NAME, err),
+                    None,
+                );
+            }
+        }
+
+        builder.build()
+    }
+
+    pub(crate) fn from_builder(builder: MyServiceBusClientBuilder) -> Self {
+        let endpoints = Arc::new(MySbEndpoints::new());
+        let connection_events = Arc::new(MySbConnectionEvents::new());
+        let rejects = Arc::new(MySbRejects::new(builder.settings.get_reject_policy()));
+        let tcp_settings = TcpConnectionSettings::new(
+            builder.settings,
+            endpoints.clone(),
+            connection_events.clone(),
+            rejects.clone(),
+        );
+
+        let metrics = Arc::new(MySbMetrics::new());
+
+        let ignored_messages = Arc::new(IgnoredMessages::new());
+
+        for rule in builder.ignored_messages {
+            ignored_messages.add(rule);
+        }
 
         let data = TcpClientData {
-            publishers: Arc::new(MySbPublishers::new()),
-            subscribers: Arc::new(MySbSubscribers::new()),
-            logger,
-            has_connection: Arc::new(AtomicBool::new(false)),
-            app_name: app_name.into(),
-            app_version: app_version.into(),
+            publishers: Arc::new(MySbPublishers::new(
+                builder.protocol_version,
+                builder.publish_retry_policy,
+                builder.publish_timeout,
+                builder.publish_in_flight_limits,
+                builder.max_packet_size,
+                metrics.clone(),
+            )),
+            subscribers: Arc::new(MySbSubscribers::new(
+                metrics.clone(),
+                builder.logger.clone(),
+                builder.unexpected_delivery_handler,
+            )),
+            logger: builder.logger,
+            connection_events,
+            app_name: builder.app_name,
+            app_version: builder.app_version,
             client_version: get_client_version(),
-            ignore_message: IgnoreMessage::new(),
+            env_info: builder.env_info,
+            protocol_version: builder.protocol_version,
+            ignored_messages,
+            endpoints,
+            rejects,
+            metrics,
         };
 
-        Self {
-            tcp_client: TcpClient::new(TCP_CLIENT_NAME.to_string(), Arc::new(tcp_settings)),
-            data,
+        let mut tcp_client = TcpClient::new(TCP_CLIENT_NAME.to_string(), Arc::new(tcp_settings));
+
+        if let Some(ping_interval) = builder.ping_interval {
+            tcp_client = tcp_client.set_seconds_to_ping(get_seconds_to_ping(ping_interval));
         }
+
+        Self { tcp_client, data }
     }
 
     pub async fn start(&self) {
@@ -106,13 +204,20 @@ impl MyServiceBusClient {
         self.data
             .publishers
             .create_topic_if_not_exists(topic_id.to_string());
-        PublisherWithInternalQueue::new(
+        let result = PublisherWithInternalQueue::new(
             topic_id.to_string(),
             self.data.publishers.clone(),
             self.data.logger.clone(),
-        )
+        );
+
+        self.data
+            .publishers
+            .register_internal_queue(&result.get_data());
+
+        result
     }
 
+    // Fails with SubscribeError::AlreadyRegistered if the queue already has a subscriber
     pub async fn subscribe<
         TModel: GetMySbModelTopicId + MySbMessageDeserializer<Item = TModel> + Send + Sync + 'static,
     >(
@@ -120,7 +225,37 @@ impl MyServiceBusClient {
         queue_id: impl Into<StrOrString<'static>>,
         queue_type: TopicQueueType,
         callback: Arc<dyn SubscriberCallback<TModel> + Send + Sync + 'static>,
-    ) {
+    ) -> Result<MySbSubscription, SubscribeError> {
+        let (topic_id, queue_id, subscriber) =
+            self.create_subscriber(queue_id, queue_type, callback);
+        self.data.subscribers.add(topic_id, queue_id, subscriber)
+    }
+
+    // Swaps the callback of an existing subscriber. Batches in flight are finished by the old callback.
+    // The queue is not resubscribed, so a different queue_type is applied only after reconnect.
+    pub async fn subscribe_or_replace<
+        TModel: GetMySbModelTopicId + MySbMessageDeserializer<Item = TModel> + Send + Sync + 'static,
+    >(
+        &self,
+        queue_id: impl Into<StrOrString<'static>>,
+        queue_type: TopicQueueType,
+        callback: Arc<dyn SubscriberCallback<TModel> + Send + Sync + 'static>,
+    ) -> MySbSubscription {
+        let (topic_id, queue_id, subscriber) =
+            self.create_subscriber(queue_id, queue_type, callback);
+        self.data
+            .subscribers
+            .replace(topic_id, queue_id, subscriber)
+    }
+
+    fn create_subscriber<
+        TModel: GetMySbModelTopicId + MySbMessageDeserializer<Item = TModel> + Send + Sync + 'static,
+    >(
+        &self,
+        queue_id: impl Into<StrOrString<'static>>,
+        queue_type: TopicQueueType,
+        callback: Arc<dyn SubscriberCallback<TModel> + Send + Sync + 'static>,
+    ) -> (&'static str, String, Arc<Subscriber<TModel>>) {
         let topic_id = TModel::get_topic_id();
         let queue_id: StrOrString<'static> = queue_id.into();
 
@@ -133,19 +268,1355 @@ impl MyServiceBusClient {
             self.data.subscribers.clone(),
         );
 
-        let subscriber = Arc::new(subscriber);
-        self.data
+        (topic_id, queue_id.to_string(), Arc::new(subscriber))
+    }
+
+    // There is no unsubscribe contract, so the session is recycled
+    // and the remaining subscribers are resubscribed on reconnect.
+    pub async fn unsubscribe(
+        &self,
+        topic_id: &str,
+        queue_id: &str,
+        timeout: Duration,
+    ) -> Result<(), UnsubscribeError> {
+        if !self.data.subscribers.remove(topic_id, queue_id) {
+            return Err(UnsubscribeError::NotSubscribed {
+                topic_id: topic_id.to_string(),
+                queue_id: queue_id.to_string(),
+            });
+        }
+
+        let deadline = Instant::now() + timeout;
+
+        wait_until(deadline, || {
+            self.data
+                .subscribers
+                .get_queue_deliveries_in_flight(topic_id, queue_id)
+                == 0
+        })
+        .await;
+
+        let deliveries_in_flight = self
+            .data
             .subscribers
-            .add(topic_id, queue_id.to_string(), subscriber);
+            .get_queue_deliveries_in_flight(topic_id, queue_id);
+
+        self.data.subscribers.recycle_connection();
+
+        if deliveries_in_flight > 0 {
+            return Err(UnsubscribeError::Timeout {
+                deliveries_in_flight,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub async fn create_rpc_client<
+        TReq: MySbMessageSerializer + GetMySbModelTopicId,
+        TResp: GetMySbModelTopicId + MySbMessageDeserializer<Item = TResp> + Send + Sync + 'static,
+    >(
+        &self,
+        reply_queue_id: impl Into<StrOrString<'static>>,
+        timeout: Duration,
+    ) -> Result<RpcClient<TReq, TResp>, SubscribeError> {
+        let reply_queue_id: StrOrString<'static> = reply_queue_id.into();
+        let pending_calls = Arc::new(RpcPendingCalls::new(reply_queue_id.to_string()));
+
+        let result = RpcClient::new(
+            self.get_publisher(false),
+            pending_calls.clone(),
+            timeout,
+            self.subscribe_to_connection_events(),
+        );
+
+        self.subscribe::<TResp>(
+            reply_queue_id,
+            TopicQueueType::DeleteOnDisconnect,
+            pending_calls,
+        )
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn start_rpc_server<
+        TReq: GetMySbModelTopicId + MySbMessageDeserializer<Item = TReq> + Send + Sync + 'static,
+        TResp: MySbMessageSerializer + GetMySbModelTopicId + Send + Sync + 'static,
+    >(
+        &self,
+        queue_id: impl Into<StrOrString<'static>>,
+        queue_type: TopicQueueType,
+        handler: Arc<dyn RpcHandler<TReq, TResp> + Send + Sync + 'static>,
+    ) -> Result<MySbSubscription, SubscribeError> {
+        let server = RpcServer::new(self.get_publisher(true), handler, self.data.logger.clone());
+
+        self.subscribe::<TReq>(queue_id, queue_type, Arc::new(server))
+            .await
     }
 
     pub fn has_connection(&self) -> bool {
+        self.data.connection_events.is_connected()
+    }
+
+    pub fn subscribe_to_connection_events(
+        &self,
+    ) -> tokio::sync::broadcast::Receiver<MySbConnectionEvent> {
+        self.data.connection_events.subscribe()
+    }
+
+    pub async fn wait_until_connected(&self, timeout: Duration) -> bool {
+        self.data
+            .connection_events
+            .wait_until_connected(timeout)
+            .await
+    }
+
+    pub fn get_reject_reason(&self) -> Option<String> {
+        self.data.rejects.get_reject_reason()
+    }
+
+    pub fn get_connected_endpoint(&self) -> Option<String> {
+        self.data.endpoints.get_connected_endpoint()
+    }
+
+    // Delivered messages with these ids are confirmed without calling the subscriber.
+    // A single id is a range with from_id == to_id.
+    pub fn ignore_messages(
+        &self,
+        topic_id: &str,
+        queue_id: &str,
+        ids_or_ranges: Vec<QueueIndexRange<i64>>,
+    ) {
         self.data
-            .has_connection
-            .load(std::sync::atomic::Ordering::SeqCst)
+            .ignored_messages
+            .add(IgnoreMessagesRule::new(topic_id, queue_id, ids_or_ranges));
+    }
+
+    // Adds the rules from the file. Returns the amount of rules loaded.
+    pub fn load_ignored_messages(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<usize, IgnoreMessagesError> {
+        let rules = IgnoreMessagesRule::load_from_file(path)?;
+        let result = rules.len();
+
+        for rule in rules {
+            self.data.ignored_messages.add(rule);
+        }
+
+        Ok(result)
+    }
+
+    pub fn remove_ignored_messages(&self, topic_id: &str, queue_id: &str) -> bool {
+        self.data.ignored_messages.remove(topic_id, queue_id)
+    }
+
+    pub fn get_ignored_messages(&self) -> Vec<IgnoreMessagesRule> {
+        self.data.ignored_messages.get_rules()
+    }
+
+    pub fn get_metrics(&self) -> MySbMetricsSnapshot {
+        self.data.metrics.get_snapshot(
+            self.data.publishers.get_requests_in_flight(),
+            self.data.publishers.get_internal_queues_metrics(),
+        )
+    }
+
+    // Subscribers are drained first so their handlers can still publish,
+    // then internal queues, then in-flight publish requests.
+    // The connection is closed even if the timeout is reached.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<(), ShutdownError> {
+        let deadline = Instant::now() + timeout;
+
+        self.data.subscribers.shutdown();
+        wait_until(deadline, || {
+            self.data.subscribers.get_deliveries_in_flight() == 0
+        })
+        .await;
+
+        self.data.publishers.shutdown_internal_queues();
+        wait_until(deadline, || {
+            self.data.publishers.get_internal_queues_size() == 0
+        })
+        .await;
+
+        self.data.publishers.stop_accepting_publishes();
+        wait_until(deadline, || {
+            self.data.publishers.get_requests_in_flight() == 0
+        })
+        .await;
+
+        let deliveries_in_flight = self.data.subscribers.get_deliveries_in_flight();
+        let messages_in_internal_queues = self.data.publishers.get_internal_queues_size();
+        let publish_requests_in_flight = self.data.publishers.get_requests_in_flight();
+
+        self.data.connection_events.shutting_down();
+        self.tcp_client.stop().await;
+
+        if deliveries_in_flight > 0
+            || messages_in_internal_queues > 0
+            || publish_requests_in_flight > 0
+        {
+            return Err(ShutdownError::Timeout {
+                deliveries_in_flight,
+                messages_in_internal_queues,
+                publish_requests_in_flight,
+            });
+        }
+
+        Ok(())
     }
 }
 
+async fn wait_until(deadline: Instant, condition: impl Fn() -> bool) {
+    while !condition() {
+        if Instant::now() >= deadline {
+            return;
+        }
+
+        tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+    }
+}
+
+// The TCP client pings in whole seconds, so the interval is rounded up and is at least 1s
+fn get_seconds_to_ping(ping_interval: Duration) -> usize {
+    let seconds = ping_interval.as_secs() + (ping_interval.subsec_nanos() > 0) as u64;
+    seconds.max(1) as usize
+}
+
 fn get_client_version() -> String {
     env!("CARGO_PKG_VERSION").to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use my_service_bus_abstractions::{
+        publisher::{InternalQueueLimits, MessageToPublish, QueueOverflowPolicy},
+        queue_with_intervals::QueueIndexRange,
+        subscriber::{
+            MessagesReader, MySbMessageDeserializer, MySbSubscriberHandleError, SubscriberCallback,
+            TopicQueueType,
+        },
+        GetMySbModelTopicId, MySbMessageSerializer, PublishError, SbMessageHeaders,
+        SubscriberError,
+    };
+    use my_service_bus_tcp_shared::{test_broker::MySbTestBroker, MySbTcpContract};
+    use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+    use super::{
+        get_seconds_to_ping, MyServiceBusClient, MyServiceBusClientBuilder, MyServiceBusSettings,
+    };
+    use crate::{
+        rpc::RpcHandler,
+        test_utils::{TestCallback, TestLogger, TestModel, TestSettings},
+        DisconnectReason, InFlightLimitPolicy, MySbConnectionEvent, PublishInFlightLimits,
+        RejectPolicy, SubscribeError,
+    };
+
+    fn new_client(broker: &MySbTestBroker) -> MyServiceBusClient {
+        MyServiceBusClient::new(
+            "test-app",
+            "1.0.0",
+            Arc::new(TestSettings(broker.get_host_port().to_string())),
+            Arc::new(TestLogger),
+        )
+    }
+
+    async fn wait_for_packet<TResult>(
+        broker: &MySbTestBroker,
+        get: impl Fn(&MySbTcpContract) -> Option<TResult>,
+    ) -> TResult {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                if let Some(result) = broker.get_received_packets().iter().find_map(&get) {
+                    return result;
+                }
+
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_after_connection_is_established() {
+        let broker = MySbTestBroker::start().await.unwrap();
+        let client = new_client(&broker);
+
+        client.start().await;
+
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        let subscription = client
+            .subscribe::<TestModel>(
+                "test-queue",
+                TopicQueueType::DeleteOnDisconnect,
+                Arc::new(TestCallback),
+            )
+            .await
+            .unwrap();
+
+        let subscribe = wait_for_packet(&broker, |packet| match packet {
+            MySbTcpContract::Subscribe {
+                topic_id, queue_id, ..
+            } => Some((topic_id.to_string(), queue_id.to_string())),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!("test-topic", subscribe.0);
+        assert_eq!("test-queue", subscribe.1);
+
+        subscription
+            .wait_until_acknowledged(Duration::from_secs(10))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_greeting_uses_builder_options() {
+        let broker = MySbTestBroker::start().await.unwrap();
+
+        let client = MyServiceBusClientBuilder::new(
+            "test-app",
+            "1.0.0",
+            Arc::new(TestSettings(broker.get_host_port().to_string())),
+            Arc::new(TestLogger),
+        )
+        .with_env_info("test-env")
+        .with_protocol_version(2)
+        .build();
+
+        client.start().await;
+
+        let greeting = wait_for_packet(&broker, |packet| match packet {
+            MySbTcpContract::Greeting {
+                name,
+                protocol_version,
+            } => Some((name.to_string(), *protocol_version)),
+            _ => None,
+        })
+        .await;
+
+        assert!(greeting.0.starts_with("test-app:1.0.0;"));
+        assert!(greeting.0.ends_with(";test-env"));
+        assert_eq!(2, greeting.1);
+    }
+
+    // Sends attempt_no of every handled message. The first batch is failed if fail_first is set.
+    struct RecordingCallback {
+        sender: UnboundedSender<i32>,
+        fail_first: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl SubscriberCallback<TestModel> for RecordingCallback {
+        async fn handle_messages(
+            &self,
+            messages_reader: &MessagesReader<TestModel>,
+        ) -> Result<(), MySbSubscriberHandleError> {
+            let fail = self
+                .fail_first
+                .swap(false, std::sync::atomic::Ordering::SeqCst);
+
+            while let Some(message) = messages_reader.get_next_message().await {
+                let _ = self.sender.send(message.attempt_no);
+
+                if fail {
+                    return Err(MySbSubscriberHandleError::AllMessagesAreNotDelivered);
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    async fn publish_test_message(client: &MyServiceBusClient) {
+        let publisher = client.get_publisher::<TestModel>(true);
+
+        tokio::time::timeout(
+            Duration::from_secs(30),
+            publisher.client.publish_message(
+                &publisher.topic_id,
+                MessageToPublish::new(vec![1]),
+                true,
+            ),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    }
+
+    async fn receive(receiver: &mut UnboundedReceiver<i32>) -> i32 {
+        tokio::time::timeout(Duration::from_secs(30), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_end_to_end_with_test_broker() {
+        let broker = MySbTestBroker::start().await.unwrap();
+
+        let client = new_client(&broker);
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        let subscription = client
+            .subscribe::<TestModel>(
+                "test-queue",
+                TopicQueueType::Permanent,
+                Arc::new(RecordingCallback {
+                    sender,
+                    fail_first: std::sync::atomic::AtomicBool::new(true),
+                }),
+            )
+            .await
+            .unwrap();
+
+        client.start().await;
+
+        subscription
+            .wait_until_acknowledged(Duration::from_secs(30))
+            .await
+            .unwrap();
+
+        publish_test_message(&client).await;
+
+        assert!(broker.topic_exists("test-topic"));
+        assert_eq!(1, broker.get_published_messages("test-topic").len());
+
+        // The first delivery is failed by the handler and the message comes back
+        assert_eq!(0, receive(&mut receiver).await);
+        assert_eq!(1, receive(&mut receiver).await);
+
+        broker.disconnect_sessions();
+
+        // The new session appears only after the client has dropped the old one
+        while broker.get_sessions_amount() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        subscription
+            .wait_until_acknowledged(Duration::from_secs(30))
+            .await
+            .unwrap();
+
+        publish_test_message(&client).await;
+
+        assert_eq!(0, receive(&mut receiver).await);
+        assert_eq!(2, broker.get_published_messages("test-topic").len());
+    }
+
+    #[tokio::test]
+    async fn test_ignored_messages_are_confirmed() {
+        let broker = MySbTestBroker::start().await.unwrap();
+
+        let client = new_client(&broker);
+
+        client.ignore_messages(
+            "test-topic",
+            "test-queue",
+            vec![QueueIndexRange {
+                from_id: 0,
+                to_id: 0,
+            }],
+        );
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        let subscription = client
+            .subscribe::<TestModel>(
+                "test-queue",
+                TopicQueueType::Permanent,
+                Arc::new(RecordingCallback {
+                    sender,
+                    fail_first: std::sync::atomic::AtomicBool::new(false),
+                }),
+            )
+            .await
+            .unwrap();
+
+        client.start().await;
+
+        subscription
+            .wait_until_acknowledged(Duration::from_secs(30))
+            .await
+            .unwrap();
+
+        publish_test_message(&client).await;
+
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while broker.get_confirmations().len() == 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            broker.get_confirmations()[0],
+            MySbTcpContract::NewMessagesConfirmation { .. }
+        ));
+        assert!(receiver.try_recv().is_err());
+
+        publish_test_message(&client).await;
+
+        assert_eq!(0, receive(&mut receiver).await);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_subscriber() {
+        let client = MyServiceBusClient::new(
+            "test-app",
+            "1.0.0",
+            Arc::new(TestSettings("127.0.0.1:1".to_string())),
+            Arc::new(TestLogger),
+        );
+
+        client
+            .subscribe::<TestModel>(
+                "test-queue",
+                TopicQueueType::Permanent,
+                Arc::new(TestCallback),
+            )
+            .await
+            .unwrap();
+
+        let result = client
+            .subscribe::<TestModel>(
+                "test-queue",
+                TopicQueueType::Permanent,
+                Arc::new(TestCallback),
+            )
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(SubscribeError::AlreadyRegistered { .. })
+        ));
+
+        let subscription = client
+            .subscribe_or_replace::<TestModel>(
+                "test-queue",
+                TopicQueueType::Permanent,
+                Arc::new(TestCallback),
+            )
+            .await;
+
+        assert_eq!("test-queue", subscription.queue_id);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_internal_queue() {
+        let broker = MySbTestBroker::start().await.unwrap();
+        let client = new_client(&broker);
+
+        let publisher = client.get_publisher_with_internal_queue::<TestModel>();
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        for _ in 0..10 {
+            publisher
+                .publish_and_forget(
+                    TestModel,
+                    #[cfg(feature = "with-telemetry")]
+                    None,
+                )
+                .unwrap();
+        }
+
+        client.shutdown(Duration::from_secs(10)).await.unwrap();
+
+        assert_eq!(10, broker.get_published_messages("test-topic").len());
+        assert_eq!(0, publisher.get_queue_size());
+
+        let result = publisher.publish_and_forget(
+            TestModel,
+            #[cfg(feature = "with-telemetry")]
+            None,
+        );
+
+        assert!(matches!(result, Err(PublishError::ShuttingDown)));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_internal_queue_is_unregistered() {
+        let broker = MySbTestBroker::start().await.unwrap();
+        let client = new_client(&broker);
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        let publisher = client.get_publisher_with_internal_queue::<TestModel>();
+
+        publisher
+            .publish_and_forget(
+                TestModel,
+                #[cfg(feature = "with-telemetry")]
+                None,
+            )
+            .unwrap();
+
+        assert!(client
+            .get_metrics()
+            .internal_queues
+            .contains_key("test-topic"));
+
+        drop(publisher);
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while client.get_metrics().internal_queues.len() > 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(1, broker.get_published_messages("test-topic").len());
+    }
+
+    async fn next_event(
+        events: &mut tokio::sync::broadcast::Receiver<MySbConnectionEvent>,
+    ) -> MySbConnectionEvent {
+        tokio::time::timeout(Duration::from_secs(10), events.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_connection_events_order() {
+        let broker = MySbTestBroker::start().await.unwrap();
+
+        let client = MyServiceBusClientBuilder::new(
+            "test-app",
+            "1.0.0",
+            Arc::new(TestSettings(broker.get_host_port().to_string())),
+            Arc::new(TestLogger),
+        )
+        .with_ping_interval(Duration::from_secs(1))
+        .build();
+
+        let mut events = client.subscribe_to_connection_events();
+
+        client.start().await;
+
+        match next_event(&mut events).await {
+            MySbConnectionEvent::Connecting { endpoint } => {
+                assert_eq!(broker.get_host_port(), endpoint)
+            }
+            event => panic!("Connecting is expected. Got: {:?}", event),
+        }
+
+        let connection_id = match next_event(&mut events).await {
+            MySbConnectionEvent::Connected { connection_id, .. } => connection_id,
+            event => panic!("Connected is expected. Got: {:?}", event),
+        };
+
+        // Nothing is subscribed, so the greeting is accepted by the Pong
+        match next_event(&mut events).await {
+            MySbConnectionEvent::GreetingAccepted { connection_id: id } => {
+                assert_eq!(connection_id, id)
+            }
+            event => panic!("GreetingAccepted is expected. Got: {:?}", event),
+        }
+
+        broker.disconnect_sessions();
+
+        match next_event(&mut events).await {
+            MySbConnectionEvent::Disconnected {
+                connection_id: id,
+                reason: DisconnectReason::ConnectionLost,
+            } => assert_eq!(connection_id, id),
+            event => panic!("Disconnected is expected. Got: {:?}", event),
+        }
+
+        assert!(matches!(
+            next_event(&mut events).await,
+            MySbConnectionEvent::Connecting { .. }
+        ));
+    }
+
+    struct StopReconnectingSettings(String);
+
+    #[async_trait::async_trait]
+    impl MyServiceBusSettings for StopReconnectingSettings {
+        async fn get_host_port(&self) -> String {
+            self.0.clone()
+        }
+
+        fn get_reject_policy(&self) -> RejectPolicy {
+            RejectPolicy::StopReconnecting
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject_with_stop_reconnecting() {
+        let broker = MySbTestBroker::start().await.unwrap();
+
+        let client = MyServiceBusClient::new(
+            "test-app",
+            "1.0.0",
+            Arc::new(StopReconnectingSettings(broker.get_host_port().to_string())),
+            Arc::new(TestLogger),
+        );
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        broker.pause_publish_responses();
+
+        let publisher = client.get_publisher::<TestModel>(false);
+
+        let in_flight = tokio::spawn(async move {
+            publisher
+                .client
+                .publish_message(&publisher.topic_id, MessageToPublish::new(vec![1]), false)
+                .await
+        });
+
+        wait_for_packet(&broker, |packet| match packet {
+            MySbTcpContract::Publish { .. } => Some(()),
+            _ => None,
+        })
+        .await;
+
+        let mut events = client.subscribe_to_connection_events();
+
+        broker.reject_sessions("test reason");
+
+        let result = tokio::time::timeout(Duration::from_secs(10), in_flight)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(result, Err(PublishError::Rejected(reason)) if reason == "test reason"));
+        assert_eq!(Some("test reason".to_string()), client.get_reject_reason());
+
+        loop {
+            if let MySbConnectionEvent::Disconnected { reason, .. } = next_event(&mut events).await
+            {
+                assert!(
+                    matches!(reason, DisconnectReason::Rejected(reason) if reason == "test reason")
+                );
+                break;
+            }
+        }
+
+        // Not reconnecting after the reject itself is covered by the MySbRejects tests
+        assert_eq!(0, broker.get_sessions_amount());
+        assert!(!client.has_connection());
+
+        let publisher = client.get_publisher::<TestModel>(false);
+        let result = publisher
+            .client
+            .publish_message(&publisher.topic_id, MessageToPublish::new(vec![1]), false)
+            .await;
+
+        assert!(matches!(result, Err(PublishError::Rejected(_))));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_to_unknown_queue_times_out() {
+        let broker = MySbTestBroker::start().await.unwrap();
+        broker.set_unknown_queue("test-topic", "unknown-queue");
+
+        let client = new_client(&broker);
+        client.start().await;
+
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        let subscription = client
+            .subscribe::<TestModel>(
+                "unknown-queue",
+                TopicQueueType::DeleteOnDisconnect,
+                Arc::new(TestCallback),
+            )
+            .await
+            .unwrap();
+
+        wait_for_packet(&broker, |packet| match packet {
+            MySbTcpContract::Subscribe { queue_id, .. } => Some(queue_id.to_string()),
+            _ => None,
+        })
+        .await;
+
+        let result = subscription
+            .wait_until_acknowledged(Duration::from_secs(1))
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(SubscribeError::Timeout { topic_id, queue_id })
+                if topic_id == "test-topic" && queue_id == "unknown-queue"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_while_waiting_for_acknowledgement() {
+        let broker = MySbTestBroker::start().await.unwrap();
+        broker.set_unknown_queue("test-topic", "unknown-queue");
+
+        let client = new_client(&broker);
+        client.start().await;
+
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        let subscription = client
+            .subscribe::<TestModel>(
+                "unknown-queue",
+                TopicQueueType::DeleteOnDisconnect,
+                Arc::new(TestCallback),
+            )
+            .await
+            .unwrap();
+
+        let waiter = tokio::spawn(async move {
+            subscription
+                .wait_until_acknowledged(Duration::from_secs(10))
+                .await
+        });
+
+        client
+            .unsubscribe("test-topic", "unknown-queue", Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        let result = waiter.await.unwrap();
+
+        assert!(matches!(
+            result,
+            Err(SubscribeError::Unsubscribed { topic_id, queue_id })
+                if topic_id == "test-topic" && queue_id == "unknown-queue"
+        ));
+    }
+
+    fn count_subscribes(broker: &MySbTestBroker, queue: &str) -> usize {
+        broker
+            .get_received_packets()
+            .iter()
+            .filter(|packet| {
+                matches!(packet, MySbTcpContract::Subscribe { queue_id, .. } if queue_id == queue)
+            })
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe_keeps_other_queues() {
+        let broker = MySbTestBroker::start().await.unwrap();
+        let client = new_client(&broker);
+
+        let (sender_a, mut receiver_a) = tokio::sync::mpsc::unbounded_channel();
+        let (sender_b, mut receiver_b) = tokio::sync::mpsc::unbounded_channel();
+
+        let subscription_a = client
+            .subscribe::<TestModel>(
+                "queue-a",
+                TopicQueueType::DeleteOnDisconnect,
+                Arc::new(RecordingCallback {
+                    sender: sender_a,
+                    fail_first: std::sync::atomic::AtomicBool::new(false),
+                }),
+            )
+            .await
+            .unwrap();
+
+        let subscription_b = client
+            .subscribe::<TestModel>(
+                "queue-b",
+                TopicQueueType::DeleteOnDisconnect,
+                Arc::new(RecordingCallback {
+                    sender: sender_b,
+                    fail_first: std::sync::atomic::AtomicBool::new(false),
+                }),
+            )
+            .await
+            .unwrap();
+
+        client.start().await;
+
+        for subscription in [&subscription_a, &subscription_b] {
+            subscription
+                .wait_until_acknowledged(Duration::from_secs(30))
+                .await
+                .unwrap();
+        }
+
+        client
+            .unsubscribe("test-topic", "queue-b", Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        // queue-a is subscribed again on the recycled session
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while count_subscribes(&broker, "queue-a") < 2 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        subscription_a
+            .wait_until_acknowledged(Duration::from_secs(30))
+            .await
+            .unwrap();
+
+        publish_test_message(&client).await;
+
+        assert_eq!(0, receive(&mut receiver_a).await);
+        assert!(receiver_b.try_recv().is_err());
+        assert_eq!(1, count_subscribes(&broker, "queue-b"));
+    }
+
+    #[test]
+    fn test_ping_interval_is_rounded_up() {
+        assert_eq!(1, get_seconds_to_ping(Duration::from_millis(0)));
+        assert_eq!(1, get_seconds_to_ping(Duration::from_millis(500)));
+        assert_eq!(1, get_seconds_to_ping(Duration::from_secs(1)));
+        assert_eq!(2, get_seconds_to_ping(Duration::from_millis(1500)));
+        assert_eq!(3, get_seconds_to_ping(Duration::from_secs(3)));
+    }
+
+    #[tokio::test]
+    async fn test_publish_timeout_frees_in_flight_capacity() {
+        let broker = MySbTestBroker::start().await.unwrap();
+
+        let client = MyServiceBusClientBuilder::new(
+            "test-app",
+            "1.0.0",
+            Arc::new(TestSettings(broker.get_host_port().to_string())),
+            Arc::new(TestLogger),
+        )
+        .with_publish_timeout(Duration::from_secs(1))
+        .build();
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        broker.pause_publish_responses();
+
+        let publisher = client.get_publisher::<TestModel>(false);
+
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            publisher.client.publish_message(
+                &publisher.topic_id,
+                MessageToPublish::new(vec![1]),
+                false,
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(result, Err(PublishError::Timeout)));
+        assert_eq!(0, client.get_metrics().publish_requests_in_flight);
+    }
+
+    #[tokio::test]
+    async fn test_publish_is_retried_with_a_new_request_id_after_timeout() {
+        let broker = MySbTestBroker::start().await.unwrap();
+
+        let client = MyServiceBusClientBuilder::new(
+            "test-app",
+            "1.0.0",
+            Arc::new(TestSettings(broker.get_host_port().to_string())),
+            Arc::new(TestLogger),
+        )
+        .with_publish_timeout(Duration::from_millis(500))
+        .build();
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        broker.pause_publish_responses();
+
+        let publisher = client.get_publisher::<TestModel>(false);
+
+        let publish = tokio::spawn(async move {
+            publisher
+                .client
+                .publish_message(&publisher.topic_id, MessageToPublish::new(vec![1]), true)
+                .await
+        });
+
+        let request_ids = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let request_ids: Vec<i64> = broker
+                    .get_received_packets()
+                    .iter()
+                    .filter_map(|packet| match packet {
+                        MySbTcpContract::Publish { request_id, .. } => Some(*request_id),
+                        _ => None,
+                    })
+                    .collect();
+
+                if request_ids.len() >= 2 {
+                    return request_ids;
+                }
+
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_ne!(request_ids[0], request_ids[1]);
+
+        // The late reply to the timed out request is ignored, the retry is confirmed by its own
+        broker.resume_publish_responses();
+
+        let result = tokio::time::timeout(Duration::from_secs(10), publish)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(result.is_ok());
+    }
+
+    fn count_publishes(broker: &MySbTestBroker) -> usize {
+        broker
+            .get_received_packets()
+            .iter()
+            .filter(|packet| matches!(packet, MySbTcpContract::Publish { .. }))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_while_waiting_for_in_flight_capacity() {
+        let broker = MySbTestBroker::start().await.unwrap();
+
+        let client = MyServiceBusClientBuilder::new(
+            "test-app",
+            "1.0.0",
+            Arc::new(TestSettings(broker.get_host_port().to_string())),
+            Arc::new(TestLogger),
+        )
+        .with_publish_in_flight_limits(PublishInFlightLimits {
+            max_requests: Some(1),
+            max_bytes: None,
+            policy: InFlightLimitPolicy::Wait,
+        })
+        .build();
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        broker.pause_publish_responses();
+
+        let mut publishes = Vec::new();
+
+        for _ in 0..2 {
+            let publisher = client.get_publisher::<TestModel>(false);
+
+            publishes.push(tokio::spawn(async move {
+                publisher
+                    .client
+                    .publish_message(&publisher.topic_id, MessageToPublish::new(vec![1]), false)
+                    .await
+            }));
+        }
+
+        wait_for_packet(&broker, |packet| match packet {
+            MySbTcpContract::Publish { .. } => Some(()),
+            _ => None,
+        })
+        .await;
+
+        // publish_sent is counted before waiting for the capacity, so the second publish is waiting
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while client
+                .get_metrics()
+                .publishes
+                .get("test-topic")
+                .map(|metrics| metrics.sent)
+                != Some(2)
+            {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(1, count_publishes(&broker));
+
+        broker.disconnect_sessions();
+
+        let mut results = Vec::new();
+
+        for publish in publishes {
+            let result = tokio::time::timeout(Duration::from_secs(10), publish)
+                .await
+                .unwrap()
+                .unwrap();
+
+            results.push(result);
+        }
+
+        assert!(results
+            .iter()
+            .any(|result| matches!(result, Err(PublishError::Disconnected))));
+        assert!(results
+            .iter()
+            .any(|result| matches!(result, Err(PublishError::NoConnectionToPublish))));
+        assert_eq!(1, count_publishes(&broker));
+    }
+
+    #[tokio::test]
+    async fn test_persist_immediately_reaches_publish_packet() {
+        let broker = MySbTestBroker::start().await.unwrap();
+        let client = new_client(&broker);
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        let publisher = client.get_publisher::<TestModel>(false);
+
+        publisher
+            .publish(
+                &TestModel,
+                #[cfg(feature = "with-telemetry")]
+                None,
+            )
+            .await
+            .unwrap();
+
+        let publisher = client
+            .get_publisher::<TestModel>(false)
+            .with_persist_immediately(true);
+
+        publisher
+            .publish(
+                &TestModel,
+                #[cfg(feature = "with-telemetry")]
+                None,
+            )
+            .await
+            .unwrap();
+
+        let flags: Vec<bool> = broker
+            .get_received_packets()
+            .iter()
+            .filter_map(|packet| match packet {
+                MySbTcpContract::Publish {
+                    persist_immediately,
+                    ..
+                } => Some(*persist_immediately),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(vec![false, true], flags);
+    }
+
+    struct RpcRequest(Vec<u8>);
+
+    impl GetMySbModelTopicId for RpcRequest {
+        fn get_topic_id() -> &'static str {
+            "rpc-request"
+        }
+    }
+
+    impl MySbMessageSerializer for RpcRequest {
+        fn serialize(
+            &self,
+            headers: Option<SbMessageHeaders>,
+        ) -> Result<(Vec<u8>, SbMessageHeaders), String> {
+            Ok((
+                self.0.clone(),
+                headers.unwrap_or_else(SbMessageHeaders::new),
+            ))
+        }
+    }
+
+    impl MySbMessageDeserializer for RpcRequest {
+        type Item = RpcRequest;
+
+        fn deserialize(src: &[u8], _: &SbMessageHeaders) -> Result<Self::Item, SubscriberError> {
+            Ok(RpcRequest(src.to_vec()))
+        }
+    }
+
+    struct RpcResponse(Vec<u8>);
+
+    impl GetMySbModelTopicId for RpcResponse {
+        fn get_topic_id() -> &'static str {
+            "rpc-response"
+        }
+    }
+
+    impl MySbMessageSerializer for RpcResponse {
+        fn serialize(
+            &self,
+            headers: Option<SbMessageHeaders>,
+        ) -> Result<(Vec<u8>, SbMessageHeaders), String> {
+            Ok((
+                self.0.clone(),
+                headers.unwrap_or_else(SbMessageHeaders::new),
+            ))
+        }
+    }
+
+    impl MySbMessageDeserializer for RpcResponse {
+        type Item = RpcResponse;
+
+        fn deserialize(src: &[u8], _: &SbMessageHeaders) -> Result<Self::Item, SubscriberError> {
+            Ok(RpcResponse(src.to_vec()))
+        }
+    }
+
+    struct ReverseHandler;
+
+    #[async_trait::async_trait]
+    impl RpcHandler<RpcRequest, RpcResponse> for ReverseHandler {
+        async fn handle(&self, request: RpcRequest) -> RpcResponse {
+            let mut result = request.0;
+            result.reverse();
+            RpcResponse(result)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rpc_round_trip() {
+        let broker = MySbTestBroker::start().await.unwrap();
+        let client = new_client(&broker);
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        client
+            .start_rpc_server::<RpcRequest, RpcResponse>(
+                "rpc-server",
+                TopicQueueType::Permanent,
+                Arc::new(ReverseHandler),
+            )
+            .await
+            .unwrap()
+            .wait_until_acknowledged(Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        let rpc_client = client
+            .create_rpc_client::<RpcRequest, RpcResponse>("rpc-client", Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        // Subscribe of the reply queue is sent before the request on the same connection
+        let response = rpc_client.call(&RpcRequest(vec![1, 2, 3])).await.unwrap();
+        assert_eq!(vec![3, 2, 1], response.0);
+
+        let response = rpc_client.call(&RpcRequest(vec![4, 5])).await.unwrap();
+        assert_eq!(vec![5, 4], response.0);
+
+        assert_eq!(0, rpc_client.get_pending_calls());
+        assert_eq!(2, broker.get_published_messages("rpc-response").len());
+    }
+
+    #[tokio::test]
+    async fn test_wait_overflow_policy() {
+        let broker = MySbTestBroker::start().await.unwrap();
+        let client = new_client(&broker);
+
+        let publisher = Arc::new(
+            client
+                .get_publisher_with_internal_queue::<TestModel>()
+                .with_queue_limits(InternalQueueLimits {
+                    max_messages: Some(1),
+                    max_bytes: None,
+                    overflow_policy: QueueOverflowPolicy::Wait,
+                }),
+        );
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        broker.pause_publish_responses();
+
+        publisher
+            .publish_and_forget(
+                TestModel,
+                #[cfg(feature = "with-telemetry")]
+                None,
+            )
+            .unwrap();
+
+        let waiting = publisher.publish_and_forget_async(
+            TestModel,
+            #[cfg(feature = "with-telemetry")]
+            None,
+        );
+        tokio::pin!(waiting);
+
+        // A zero timeout polls the publish once: it must be waiting for space
+        assert!(tokio::time::timeout(Duration::ZERO, &mut waiting)
+            .await
+            .is_err());
+
+        // The sync API can not wait
+        let result = publisher.publish_and_forget(
+            TestModel,
+            #[cfg(feature = "with-telemetry")]
+            None,
+        );
+        assert!(matches!(result, Err(PublishError::QueueIsFull)));
+
+        broker.resume_publish_responses();
+
+        tokio::time::timeout(Duration::from_secs(10), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), publisher.flush())
+            .await
+            .unwrap();
+
+        assert_eq!(2, broker.get_published_messages("test-topic").len());
+    }
+
+    #[tokio::test]
+    async fn test_permanent_publish_error_completes_receipt() {
+        let broker = MySbTestBroker::start().await.unwrap();
+
+        let client = MyServiceBusClientBuilder::new(
+            "test-app",
+            "1.0.0",
+            Arc::new(TestSettings(broker.get_host_port().to_string())),
+            Arc::new(TestLogger),
+        )
+        .with_max_packet_size(1)
+        .build();
+
+        let publisher = client.get_publisher_with_internal_queue::<TestModel>();
+
+        client.start().await;
+        assert!(client.wait_until_connected(Duration::from_secs(10)).await);
+
+        let receipt = publisher
+            .publish_with_receipt(
+                TestModel,
+                #[cfg(feature = "with-telemetry")]
+                None,
+            )
+            .unwrap();
+
+        let result = tokio::time::timeout(Duration::from_secs(10), receipt.wait())
+            .await
+            .unwrap();
+
+        assert!(matches!(result, Err(PublishError::MessageTooLarge { .. })));
+
+        tokio::time::timeout(Duration::from_secs(10), publisher.flush())
+            .await
+            .unwrap();
+
+        assert_eq!(0, publisher.get_queue_size());
+        assert_eq!(1, publisher.get_dropped_messages());
+        assert_eq!(0, broker.get_published_messages("test-topic").len());
+    }
+}
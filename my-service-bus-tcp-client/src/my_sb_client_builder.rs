@@ -0,0 +1,116 @@
+use std::{sync::Arc, time::Duration};
+
+use my_service_bus_tcp_shared::DEFAULT_TCP_PROTOCOL_VERSION;
+use rust_extensions::{Logger, StrOrString};
+
+use crate::{
+    IgnoreMessagesRule, MyServiceBusClient, MyServiceBusSettings, PublishInFlightLimits,
+    PublishRetryPolicy, UnexpectedDeliveryHandler,
+};
+
+pub struct MyServiceBusClientBuilder {
+    pub(crate) app_name: StrOrString<'static>,
+    pub(crate) app_version: StrOrString<'static>,
+    pub(crate) settings: Arc<dyn MyServiceBusSettings + Send + Sync + 'static>,
+    pub(crate) logger: Arc<dyn Logger + Send + Sync + 'static>,
+    pub(crate) env_info: Option<String>,
+    pub(crate) ignored_messages: Vec<IgnoreMessagesRule>,
+    pub(crate) publish_retry_policy: PublishRetryPolicy,
+    pub(crate) publish_timeout: Option<Duration>,
+    pub(crate) publish_in_flight_limits: PublishInFlightLimits,
+    pub(crate) max_packet_size: Option<usize>,
+    pub(crate) ping_interval: Option<Duration>,
+    pub(crate) protocol_version: i32,
+    pub(crate) unexpected_delivery_handler:
+        Option<Arc<dyn UnexpectedDeliveryHandler + Send + Sync + 'static>>,
+}
+
+impl MyServiceBusClientBuilder {
+    pub fn new(
+        app_name: impl Into<StrOrString<'static>>,
+        app_version: impl Into<StrOrString<'static>>,
+        settings: Arc<dyn MyServiceBusSettings + Send + Sync + 'static>,
+        logger: Arc<dyn Logger + Send + Sync + 'static>,
+    ) -> Self {
+        Self {
+            app_name: app_name.into(),
+            app_version: app_version.into(),
+            settings,
+            logger,
+            env_info: None,
+            ignored_messages: Vec::new(),
+            publish_retry_policy: PublishRetryPolicy::default(),
+            publish_timeout: None,
+            publish_in_flight_limits: PublishInFlightLimits::default(),
+            max_packet_size: None,
+            ping_interval: None,
+            protocol_version: DEFAULT_TCP_PROTOCOL_VERSION,
+            unexpected_delivery_handler: None,
+        }
+    }
+
+    // Appended to the greeting name. Replaces the ENV_INFO env variable.
+    pub fn with_env_info(mut self, env_info: impl Into<String>) -> Self {
+        self.env_info = Some(env_info.into());
+        self
+    }
+
+    // Replaces the SB_IGNORE_MESSAGE env variable. Can be called several times.
+    // Rules can be changed at runtime with MyServiceBusClient::ignore_messages.
+    pub fn with_ignored_messages(mut self, rule: IgnoreMessagesRule) -> Self {
+        self.ignored_messages.push(rule);
+        self
+    }
+
+    // Used by publishers with do_retries = true while the connection is restored.
+    pub fn with_publish_retry_policy(mut self, publish_retry_policy: PublishRetryPolicy) -> Self {
+        self.publish_retry_policy = publish_retry_policy;
+        self
+    }
+
+    // Fails a publish with PublishError::Timeout if the server does not confirm it in time.
+    // Publishers with do_retries = true retry it according to the publish retry policy.
+    pub fn with_publish_timeout(mut self, publish_timeout: Duration) -> Self {
+        self.publish_timeout = Some(publish_timeout);
+        self
+    }
+
+    // Limits publish requests and bytes waiting for confirmation per connection.
+    pub fn with_publish_in_flight_limits(mut self, limits: PublishInFlightLimits) -> Self {
+        self.publish_in_flight_limits = limits;
+        self
+    }
+
+    // Bigger publish_messages calls are split into several packets, so such a call is not atomic:
+    // on error, a prefix of the messages may already be published.
+    // A single message above the limit fails with PublishError::MessageTooLarge.
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = Some(max_packet_size);
+        self
+    }
+
+    // If not set, the TCP client's default ping interval is used.
+    // Pings are sent in whole seconds: the interval is rounded up, and anything below 1s becomes 1s.
+    pub fn with_ping_interval(mut self, ping_interval: Duration) -> Self {
+        self.ping_interval = Some(ping_interval);
+        self
+    }
+
+    pub fn with_protocol_version(mut self, protocol_version: i32) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    // Called for NewMessages to a topic/queue without a subscriber, after the batch is confirmed as failed.
+    pub fn with_unexpected_delivery_handler(
+        mut self,
+        handler: Arc<dyn UnexpectedDeliveryHandler + Send + Sync + 'static>,
+    ) -> Self {
+        self.unexpected_delivery_handler = Some(handler);
+        self
+    }
+
+    pub fn build(self) -> MyServiceBusClient {
+        MyServiceBusClient::from_builder(self)
+    }
+}
@@ -1,8 +1,6 @@
 use std::collections::HashMap;
 
-use my_service_bus_tcp_shared::{
-    MySbSerializerState, MySbTcpContract, MySbTcpSerializer, DEFAULT_TCP_PROTOCOL_VERSION,
-};
+use my_service_bus_tcp_shared::{MySbSerializerState, MySbTcpContract, MySbTcpSerializer};
 use my_tcp_sockets::tcp_connection::TcpSocketConnection;
 
 pub fn send_greeting(
@@ -10,17 +8,19 @@ pub fn send_greeting(
     app_name: &str,
     app_version: &str,
     client_version: &str,
+    env_info: Option<&str>,
+    protocol_version: i32,
 ) {
     let mut name = format!("{}:{};{}", app_name, app_version, client_version);
 
-    if let Ok(value) = std::env::var("ENV_INFO") {
+    if let Some(value) = env_info {
         name.push(';');
-        name.push_str(&value);
+        name.push_str(value);
     }
 
     let greeting = MySbTcpContract::Greeting {
         name,
-        protocol_version: DEFAULT_TCP_PROTOCOL_VERSION,
+        protocol_version,
     };
 
     socket_ctx.send(&greeting);
@@ -0,0 +1,84 @@
+#[derive(Debug, Clone, Copy)]
+pub enum InFlightLimitPolicy {
+    Wait,
+    FailFast,
+}
+
+#[derive(Debug, Clone)]
+pub struct PublishInFlightLimits {
+    pub max_requests: Option<usize>,
+    pub max_bytes: Option<usize>,
+    pub policy: InFlightLimitPolicy,
+}
+
+impl Default for PublishInFlightLimits {
+    fn default() -> Self {
+        Self {
+            max_requests: None,
+            max_bytes: None,
+            policy: InFlightLimitPolicy::Wait,
+        }
+    }
+}
+
+impl PublishInFlightLimits {
+    // A single request is always let through, so a payload bigger than max_bytes can not get stuck.
+    pub fn has_capacity(
+        &self,
+        requests_in_flight: usize,
+        bytes_in_flight: usize,
+        payload_size: usize,
+    ) -> bool {
+        if requests_in_flight == 0 {
+            return true;
+        }
+
+        if let Some(max_requests) = self.max_requests {
+            if requests_in_flight >= max_requests {
+                return false;
+            }
+        }
+
+        if let Some(max_bytes) = self.max_bytes {
+            if bytes_in_flight + payload_size > max_bytes {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PublishInFlightLimits;
+
+    #[test]
+    fn test_no_limits_by_default() {
+        let limits = PublishInFlightLimits::default();
+        assert!(limits.has_capacity(1_000_000, usize::MAX / 2, 1024));
+    }
+
+    #[test]
+    fn test_max_requests() {
+        let limits = PublishInFlightLimits {
+            max_requests: Some(2),
+            ..Default::default()
+        };
+
+        assert!(limits.has_capacity(1, 0, 10));
+        assert!(!limits.has_capacity(2, 0, 10));
+    }
+
+    #[test]
+    fn test_max_bytes() {
+        let limits = PublishInFlightLimits {
+            max_bytes: Some(100),
+            ..Default::default()
+        };
+
+        assert!(limits.has_capacity(1, 50, 50));
+        assert!(!limits.has_capacity(1, 50, 51));
+        assert!(limits.has_capacity(0, 0, 1_000));
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone)]
+pub struct PublishRetryPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    // 0.0 - no jitter, 0.5 - delay is randomized within +/-50%
+    pub jitter: f64,
+    pub max_total_wait: Option<Duration>,
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for PublishRetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_total_wait: None,
+            max_attempts: None,
+        }
+    }
+}
+
+impl PublishRetryPolicy {
+    pub fn get_delay(&self, attempt: usize) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as usize) as i32;
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = delay.min(self.max_delay.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let delay = delay * (1.0 + jitter * (2.0 * get_random() - 1.0));
+
+        Duration::from_secs_f64(delay.max(0.0))
+    }
+
+    pub fn attempts_are_exhausted(&self, attempts: usize) -> bool {
+        match self.max_attempts {
+            Some(max_attempts) => attempts >= max_attempts,
+            None => false,
+        }
+    }
+}
+
+// Every call takes the next value of a process-wide sequence, so retries started
+// at the same moment still get different delays. Time and pid separate the processes.
+static RANDOM_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+fn get_random() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+
+    let sequence = RANDOM_SEQUENCE.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed);
+
+    let mut value = nanos ^ ((std::process::id() as u64) << 32) ^ sequence;
+    value ^= value >> 30;
+    value = value.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value ^= value >> 27;
+    value = value.wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^= value >> 31;
+
+    (value % 1_000_000) as f64 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use super::PublishRetryPolicy;
+
+    #[test]
+    fn test_delay_grows_exponentially_up_to_max_delay() {
+        let policy = PublishRetryPolicy {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.0,
+            max_total_wait: None,
+            max_attempts: None,
+        };
+
+        assert_eq!(Duration::from_millis(100), policy.get_delay(1));
+        assert_eq!(Duration::from_millis(200), policy.get_delay(2));
+        assert_eq!(Duration::from_millis(400), policy.get_delay(3));
+        assert_eq!(Duration::from_secs(1), policy.get_delay(10));
+        assert_eq!(Duration::from_secs(1), policy.get_delay(usize::MAX));
+    }
+
+    #[test]
+    fn test_jitter_keeps_delay_within_bounds() {
+        let policy = PublishRetryPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_total_wait: None,
+            max_attempts: None,
+        };
+
+        for attempt in 1..100 {
+            let delay = policy.get_delay(attempt);
+            assert!(delay >= Duration::from_millis(500));
+            assert!(delay <= Duration::from_millis(1500));
+        }
+    }
+
+    #[test]
+    fn test_simultaneous_delays_are_different() {
+        let policy = PublishRetryPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_total_wait: None,
+            max_attempts: None,
+        };
+
+        let delays: HashSet<Duration> = (0..10).map(|_| policy.get_delay(1)).collect();
+        assert!(delays.len() > 1);
+    }
+
+    #[test]
+    fn test_attempts_are_exhausted() {
+        let mut policy = PublishRetryPolicy::default();
+        assert!(!policy.attempts_are_exhausted(1_000));
+
+        policy.max_attempts = Some(3);
+        assert!(!policy.attempts_are_exhausted(2));
+        assert!(policy.attempts_are_exhausted(3));
+    }
+}
@@ -1,7 +1,9 @@
 mod my_sb_publisher;
 mod my_sb_publisher_data;
+mod publish_packets;
 mod publish_process_by_connection;
 
 pub use my_sb_publisher::MySbPublishers;
 pub use my_sb_publisher_data::MySbPublisherData;
 pub use publish_process_by_connection::PublishProcessByConnection;
+pub use publish_packets::split_into_packets;
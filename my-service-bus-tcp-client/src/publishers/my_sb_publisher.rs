@@ -1,32 +1,61 @@
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
 
 use my_service_bus_abstractions::{
-    publisher::MessageToPublish, MyServiceBusPublisherClient, PublishError,
+    publisher::{InternalQueueMetrics, MessageToPublish, PublisherWithInternalQueueData},
+    MyServiceBusPublisherClient, PublishError,
 };
 use my_service_bus_tcp_shared::{MySbTcpConnection, MySbTcpContract};
 use parking_lot::Mutex;
+use rust_extensions::TaskCompletionAwaiter;
+use tokio::{sync::Notify, time::Instant};
 
+use crate::{InFlightLimitPolicy, MySbMetrics, PublishInFlightLimits, PublishRetryPolicy};
 
 use super::{MySbPublisherData, PublishProcessByConnection};
 
 pub struct MySbPublishers {
-    data: Mutex<MySbPublisherData>,
+    data: Arc<Mutex<MySbPublisherData>>,
+    protocol_version: i32,
+    retry_policy: PublishRetryPolicy,
+    publish_timeout: Option<Duration>,
+    in_flight_limits: PublishInFlightLimits,
+    in_flight_released: Arc<Notify>,
+    max_packet_size: Option<usize>,
+    metrics: Arc<MySbMetrics>,
 }
 
 impl MySbPublishers {
-    pub fn new() -> Self {
+    pub fn new(
+        protocol_version: i32,
+        retry_policy: PublishRetryPolicy,
+        publish_timeout: Option<Duration>,
+        in_flight_limits: PublishInFlightLimits,
+        max_packet_size: Option<usize>,
+        metrics: Arc<MySbMetrics>,
+    ) -> Self {
         let data = MySbPublisherData::new();
         Self {
-            data: Mutex::new(data),
+            data: Arc::new(Mutex::new(data)),
+            protocol_version,
+            retry_policy,
+            publish_timeout,
+            in_flight_limits,
+            in_flight_released: Arc::new(Notify::new()),
+            max_packet_size,
+            metrics,
         }
     }
 
     pub fn set_confirmed(&self, request_id: i64) {
-        let mut write_access = self.data.lock();
-        write_access.confirm(request_id);
+        {
+            let mut write_access = self.data.lock();
+            write_access.confirm(request_id);
+        }
+
+        self.in_flight_released.notify_waiters();
     }
 
-    pub  fn new_connection(&self, connection: Arc<MySbTcpConnection>) {
+    pub fn new_connection(&self, connection: Arc<MySbTcpConnection>) {
         {
             let mut write_access = self.data.lock();
             write_access.connection = Some(PublishProcessByConnection::new(connection.clone()));
@@ -39,8 +68,21 @@ impl MySbPublishers {
     }
 
     pub fn disconnect(&self) {
-        let mut write_access = self.data.lock();
-        write_access.disconnect();
+        {
+            let mut write_access = self.data.lock();
+            write_access.disconnect();
+        }
+
+        self.in_flight_released.notify_waiters();
+    }
+
+    pub fn rejected(&self, reason: String, reconnect_is_stopped: bool) {
+        {
+            let mut write_access = self.data.lock();
+            write_access.rejected(reason, reconnect_is_stopped);
+        }
+
+        self.in_flight_released.notify_waiters();
     }
 
     pub fn create_topic_if_not_exists(&self, topic_id: String) {
@@ -59,20 +101,126 @@ impl MySbPublishers {
         result
     }
 
-    async fn wait_until_connection_is_restored(&self) {
+    // Internal queues are kept until their publisher is dropped and the queue is drained
+    pub fn register_internal_queue(&self, internal_queue: &Arc<PublisherWithInternalQueueData>) {
+        let mut write_access = self.data.lock();
+        write_access
+            .internal_queues
+            .retain(|itm| itm.strong_count() > 0);
+        write_access
+            .internal_queues
+            .push(Arc::downgrade(internal_queue));
+    }
+
+    fn get_internal_queues(&self) -> Vec<Arc<PublisherWithInternalQueueData>> {
+        let read_access = self.data.lock();
+        read_access
+            .internal_queues
+            .iter()
+            .filter_map(|itm| itm.upgrade())
+            .collect()
+    }
+
+    pub fn shutdown_internal_queues(&self) {
+        for internal_queue in self.get_internal_queues() {
+            internal_queue.shutdown();
+        }
+    }
+
+    pub fn get_internal_queues_size(&self) -> usize {
+        self.get_internal_queues()
+            .iter()
+            .map(|itm| itm.get_queue_size())
+            .sum()
+    }
+
+    pub fn get_internal_queues_metrics(&self) -> BTreeMap<String, InternalQueueMetrics> {
+        let mut result: BTreeMap<String, InternalQueueMetrics> = BTreeMap::new();
+
+        for internal_queue in self.get_internal_queues() {
+            result
+                .entry(internal_queue.topic_id.to_string())
+                .or_default()
+                .merge(&internal_queue.get_metrics());
+        }
+
+        result
+    }
+
+    pub fn stop_accepting_publishes(&self) {
+        let mut write_access = self.data.lock();
+        write_access.is_shutting_down = true;
+    }
+
+    pub fn get_requests_in_flight(&self) -> usize {
+        let read_access = self.data.lock();
+        read_access.get_requests_in_flight()
+    }
+
+    // The request is removed on timeout, so it does not hold in-flight capacity
+    async fn wait_for_publish_result(
+        &self,
+        awaiter: TaskCompletionAwaiter<(), PublishError>,
+        request_id: i64,
+    ) -> Result<(), PublishError> {
+        let publish_timeout = match self.publish_timeout {
+            Some(publish_timeout) => publish_timeout,
+            None => return awaiter.get_result().await,
+        };
+
+        match tokio::time::timeout(publish_timeout, awaiter.get_result()).await {
+            Ok(result) => result,
+            Err(_) => {
+                {
+                    let mut write_access = self.data.lock();
+                    write_access.timeout(request_id);
+                }
+
+                self.in_flight_released.notify_waiters();
+
+                Err(PublishError::Timeout)
+            }
+        }
+    }
+
+    fn is_reconnect_stopped(&self) -> bool {
+        let read_access = self.data.lock();
+        read_access.rejected.is_some()
+    }
+
+    fn has_connection(&self) -> bool {
+        let read_access = self.data.lock();
+        read_access.connection.is_some()
+    }
+
+    // Returns false if the retry budget is exhausted
+    async fn wait_until_connection_is_restored(&self, attempts: usize, started: Instant) -> bool {
+        if self.retry_policy.attempts_are_exhausted(attempts) {
+            return false;
+        }
+
+        let mut delay_no = attempts;
+
         loop {
-            let has_connection = {
-                let read_access = self.data.lock();
-                read_access.connection.is_some()
-            };
+            let mut delay = self.retry_policy.get_delay(delay_no);
+
+            if let Some(max_total_wait) = self.retry_policy.max_total_wait {
+                let remains = max_total_wait.saturating_sub(started.elapsed());
+
+                if remains.is_zero() {
+                    return false;
+                }
 
-            if has_connection {
-                return;
+                delay = delay.min(remains);
             }
 
-            println!("Trying to restore connection to MyServiceBus");
+            tokio::time::sleep(delay).await;
 
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            if self.has_connection() {
+                return true;
+            }
+
+            delay_no += 1;
         }
     }
 }
@@ -85,7 +233,9 @@ impl MyServiceBusPublisherClient for MySbPublishers {
         message: MessageToPublish,
         do_retry: bool,
     ) -> Result<(), PublishError> {
-        return self.publish_messages(topic_id, &[message], do_retry).await;
+        return self
+            .publish_messages_with_options(topic_id, &[message], do_retry, false)
+            .await;
     }
 
     async fn publish_messages(
@@ -94,46 +244,131 @@ impl MyServiceBusPublisherClient for MySbPublishers {
         messages: &[MessageToPublish],
         do_retries: bool,
     ) -> Result<(), PublishError> {
-        let mut to_send = None;
+        return self
+            .publish_messages_with_options(topic_id, messages, do_retries, false)
+            .await;
+    }
+
+    async fn publish_message_with_options(
+        &self,
+        topic_id: &str,
+        message: MessageToPublish,
+        do_retry: bool,
+        persist_immediately: bool,
+    ) -> Result<(), PublishError> {
+        return self
+            .publish_messages_with_options(topic_id, &[message], do_retry, persist_immediately)
+            .await;
+    }
+
+    async fn publish_messages_with_options(
+        &self,
+        topic_id: &str,
+        messages: &[MessageToPublish],
+        do_retries: bool,
+        persist_immediately: bool,
+    ) -> Result<(), PublishError> {
+        // Counted per call, like confirmed and failed, whatever the packets and retries are
+        self.metrics.publish_sent(topic_id);
+
+        let result = self
+            .publish_packets(topic_id, messages, do_retries, persist_immediately)
+            .await;
+
+        if result.is_ok() {
+            self.metrics.publish_confirmed(topic_id);
+        } else {
+            self.metrics.publish_failed(topic_id);
+        }
+
+        result
+    }
+}
+
+impl MySbPublishers {
+    // Packets are published one by one to keep the order of messages.
+    // Publishing is not atomic: if a packet fails, the packets before it stay published
+    // and the error does not tell how many, so retrying the call duplicates them.
+    async fn publish_packets(
+        &self,
+        topic_id: &str,
+        messages: &[MessageToPublish],
+        do_retries: bool,
+        persist_immediately: bool,
+    ) -> Result<(), PublishError> {
+        let packets = super::split_into_packets(
+            topic_id,
+            messages,
+            self.max_packet_size,
+            self.protocol_version,
+        )?;
+
+        for packet in packets {
+            self.publish_with_retries(topic_id, packet, do_retries, persist_immediately)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn publish_with_retries(
+        &self,
+        topic_id: &str,
+        messages: &[MessageToPublish],
+        do_retries: bool,
+        persist_immediately: bool,
+    ) -> Result<(), PublishError> {
+        let started = Instant::now();
+        let mut attempts = 0;
 
         loop {
+            let in_flight_released = self.in_flight_released.notified();
+
+            // Compiled on every attempt: shutdown and reject are checked again,
+            // and a retry gets a new request_id, so a late reply to the previous one is ignored
             let awaiter_result = {
                 let mut write_access = self.data.lock();
 
-                let result = if to_send.is_none() {
-                    let result = write_access
-                        .compile_publish_payload(
-                            topic_id,
-                            messages,
-                            my_service_bus_tcp_shared::DEFAULT_TCP_PROTOCOL_VERSION.into(),
-                        );
-
-                    match result {
-                        Ok(result) => {
-                            to_send = Some(result);
-                            Ok(())
+                match write_access.compile_publish_payload(
+                    topic_id,
+                    messages,
+                    persist_immediately,
+                    self.protocol_version.into(),
+                ) {
+                    Ok((request_id, mut tcp_contract)) => {
+                        if write_access
+                            .has_in_flight_capacity(&self.in_flight_limits, &tcp_contract)
+                        {
+                            let result = write_access
+                                .publish_to_socket(&mut tcp_contract, request_id)
+                                .map(|awaiter| (awaiter, request_id));
+
+                            Some(result)
+                        } else {
+                            match self.in_flight_limits.policy {
+                                InFlightLimitPolicy::Wait => None,
+                                InFlightLimitPolicy::FailFast => {
+                                    Some(Err(PublishError::InFlightLimitReached))
+                                }
+                            }
                         }
-                        Err(err) => Err(err),
                     }
-                } else {
-                    Ok(())
-                };
-
-                match result {
-                    Ok(_) => {
-                        let (request_id, tcp_contract) = to_send.as_mut().unwrap();
-
-                        let awaiter = write_access
-                            .publish_to_socket(tcp_contract, *request_id);
+                    Err(err) => Some(Err(err)),
+                }
+            };
 
-                        Ok(awaiter)
-                    }
-                    Err(err) => Err(err),
+            let awaiter_result = match awaiter_result {
+                Some(awaiter_result) => awaiter_result,
+                None => {
+                    in_flight_released.await;
+                    continue;
                 }
             };
 
             let result = match awaiter_result {
-                Ok(awaiter) => awaiter.get_result().await,
+                Ok((awaiter, request_id)) => {
+                    self.wait_for_publish_result(awaiter, request_id).await
+                }
                 Err(err) => Err(err),
             };
 
@@ -145,16 +380,60 @@ impl MyServiceBusPublisherClient for MySbPublishers {
                 return result;
             }
 
-            if result.is_ok() {
-                return Ok(());
-            }
-
             match result.unwrap_err() {
-                PublishError::NoConnectionToPublish => {
-                    self.wait_until_connection_is_restored().await;
+                PublishError::NoConnectionToPublish
+                | PublishError::Disconnected
+                | PublishError::Timeout => {
+                    attempts += 1;
+
+                    if !self
+                        .wait_until_connection_is_restored(attempts, started)
+                        .await
+                    {
+                        return Err(PublishError::RetriesExhausted {
+                            attempts,
+                            elapsed: started.elapsed(),
+                        });
+                    }
+                }
+                PublishError::ShuttingDown => {
+                    return Err(PublishError::ShuttingDown);
+                }
+                PublishError::Rejected(reason) => {
+                    // With RejectPolicy::BackOff the client reconnects, so it is retried like a lost connection
+                    if self.is_reconnect_stopped() {
+                        return Err(PublishError::Rejected(reason));
+                    }
+
+                    attempts += 1;
+
+                    if !self
+                        .wait_until_connection_is_restored(attempts, started)
+                        .await
+                    {
+                        return Err(PublishError::RetriesExhausted {
+                            attempts,
+                            elapsed: started.elapsed(),
+                        });
+                    }
+                }
+                PublishError::InFlightLimitReached => {
+                    return Err(PublishError::InFlightLimitReached);
+                }
+                PublishError::QueueIsFull => {
+                    return Err(PublishError::QueueIsFull);
+                }
+                PublishError::MessageTooLarge {
+                    size,
+                    max_packet_size,
+                } => {
+                    return Err(PublishError::MessageTooLarge {
+                        size,
+                        max_packet_size,
+                    });
                 }
-                PublishError::Disconnected => {
-                    self.wait_until_connection_is_restored().await;
+                PublishError::RetriesExhausted { attempts, elapsed } => {
+                    return Err(PublishError::RetriesExhausted { attempts, elapsed });
                 }
                 PublishError::Other(other) => {
                     return Err(PublishError::Other(other));
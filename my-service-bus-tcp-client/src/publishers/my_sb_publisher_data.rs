@@ -1,15 +1,23 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Weak};
 
-use my_service_bus_abstractions::{publisher::MessageToPublish, PublishError};
+use my_service_bus_abstractions::{
+    publisher::{MessageToPublish, PublisherWithInternalQueueData},
+    PublishError,
+};
 use my_service_bus_tcp_shared::MySbTcpContract;
 use rust_extensions::{TaskCompletion, TaskCompletionAwaiter};
 
+use crate::PublishInFlightLimits;
+
 use super::PublishProcessByConnection;
 
 pub struct MySbPublisherData {
     request_id: i64,
     pub connection: Option<PublishProcessByConnection>,
     pub topics_to_create: HashMap<String, i32>,
+    pub internal_queues: Vec<Weak<PublisherWithInternalQueueData>>,
+    pub is_shutting_down: bool,
+    pub rejected: Option<String>,
 }
 
 impl MySbPublisherData {
@@ -18,6 +26,9 @@ impl MySbPublisherData {
             request_id: 0,
             connection: None,
             topics_to_create: HashMap::new(),
+            internal_queues: Vec::new(),
+            is_shutting_down: false,
+            rejected: None,
         }
     }
 
@@ -30,8 +41,17 @@ impl MySbPublisherData {
         &mut self,
         topic_id: &str,
         messages: &[MessageToPublish],
+        persist_immediately: bool,
         protocol_version: my_service_bus_tcp_shared::TcpProtocolVersion,
     ) -> Result<(i64, MySbTcpContract), PublishError> {
+        if self.is_shutting_down {
+            return Err(PublishError::ShuttingDown);
+        }
+
+        if let Some(reason) = self.rejected.as_ref() {
+            return Err(PublishError::Rejected(reason.to_string()));
+        }
+
         if self.connection.is_none() {
             return Err(PublishError::NoConnectionToPublish);
         }
@@ -45,7 +65,7 @@ impl MySbPublisherData {
             topic_id,
             request_id,
             messages,
-            false,
+            persist_immediately,
             protocol_version,
         );
 
@@ -56,28 +76,81 @@ impl MySbPublisherData {
         &mut self,
         tcp_contract: &mut MySbTcpContract,
         request_id: i64,
-    ) -> TaskCompletionAwaiter<(), PublishError> {
-        let connection = self.connection.as_mut().unwrap();
+    ) -> Result<TaskCompletionAwaiter<(), PublishError>, PublishError> {
+        let payload_size = get_payload_size(tcp_contract);
+        let connection = match self.connection.as_mut() {
+            Some(connection) => connection,
+            None => return Err(PublishError::NoConnectionToPublish),
+        };
 
         connection.socket.send(tcp_contract);
 
         let mut task = TaskCompletion::new();
         let awaiter = task.get_awaiter();
 
-        connection.requests.insert(request_id, task);
+        connection.add_request(request_id, task, payload_size);
 
-        awaiter
+        Ok(awaiter)
+    }
+
+    pub fn has_in_flight_capacity(
+        &self,
+        limits: &PublishInFlightLimits,
+        tcp_contract: &MySbTcpContract,
+    ) -> bool {
+        match self.connection.as_ref() {
+            Some(connection) => limits.has_capacity(
+                connection.requests.len(),
+                connection.bytes_in_flight,
+                get_payload_size(tcp_contract),
+            ),
+            None => false,
+        }
     }
 
     pub fn confirm(&mut self, request_id: i64) {
         if let Some(connection) = self.connection.as_mut() {
-            if let Some(mut request) = connection.requests.remove(&request_id) {
+            if let Some(mut request) = connection.remove_request(request_id) {
                 request.set_ok(());
             }
         }
     }
 
+    pub fn timeout(&mut self, request_id: i64) {
+        if let Some(connection) = self.connection.as_mut() {
+            if let Some(mut request) = connection.remove_request(request_id) {
+                request.set_error(PublishError::Timeout);
+            }
+        }
+    }
+
+    pub fn rejected(&mut self, reason: String, reconnect_is_stopped: bool) {
+        if let Some(connection) = self.connection.as_mut() {
+            for mut task in connection.remove_all_requests() {
+                task.set_error(PublishError::Rejected(reason.to_string()));
+            }
+        }
+
+        if reconnect_is_stopped {
+            self.rejected = Some(reason);
+        }
+    }
+
+    pub fn get_requests_in_flight(&self) -> usize {
+        match self.connection.as_ref() {
+            Some(connection) => connection.requests.len(),
+            None => 0,
+        }
+    }
+
     pub fn disconnect(&mut self) {
         self.connection = None;
     }
 }
+
+fn get_payload_size(tcp_contract: &MySbTcpContract) -> usize {
+    match tcp_contract {
+        MySbTcpContract::Raw(payload) => payload.len(),
+        _ => 0,
+    }
+}
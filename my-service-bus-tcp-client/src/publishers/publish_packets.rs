@@ -0,0 +1,95 @@
+use my_service_bus_abstractions::{publisher::MessageToPublish, PublishError};
+use my_service_bus_tcp_shared::{tcp_serializers::messages_to_publish, MySbTcpContract};
+
+pub fn split_into_packets<'s>(
+    topic_id: &str,
+    messages: &'s [MessageToPublish],
+    max_packet_size: Option<usize>,
+    protocol_version: i32,
+) -> Result<Vec<&'s [MessageToPublish]>, PublishError> {
+    let max_packet_size = match max_packet_size {
+        Some(max_packet_size) => max_packet_size,
+        None => return Ok(vec![messages]),
+    };
+
+    let overhead = MySbTcpContract::get_publish_payload_overhead(topic_id);
+
+    let mut result = Vec::new();
+    let mut packet_start = 0;
+    let mut packet_size = overhead;
+
+    for (index, message) in messages.iter().enumerate() {
+        let message_size =
+            messages_to_publish::get_serialized_size(message, protocol_version.into());
+
+        if overhead + message_size > max_packet_size {
+            return Err(PublishError::MessageTooLarge {
+                size: overhead + message_size,
+                max_packet_size,
+            });
+        }
+
+        if packet_size + message_size > max_packet_size {
+            result.push(&messages[packet_start..index]);
+            packet_start = index;
+            packet_size = overhead;
+        }
+
+        packet_size += message_size;
+    }
+
+    result.push(&messages[packet_start..]);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use my_service_bus_abstractions::{publisher::MessageToPublish, PublishError};
+    use my_service_bus_tcp_shared::MySbTcpContract;
+
+    const PROTOCOL_VERSION: i32 = 2;
+
+    // With protocol version 2 each message takes 4 bytes of length + content
+    fn get_max_packet_size(content_size: usize) -> usize {
+        MySbTcpContract::get_publish_payload_overhead("topic") + content_size
+    }
+
+    #[test]
+    fn test_no_limit() {
+        let messages = vec![MessageToPublish::new(vec![0; 10]); 3];
+        let result = super::split_into_packets("topic", &messages, None, PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(1, result.len());
+        assert_eq!(3, result[0].len());
+    }
+
+    #[test]
+    fn test_split_into_packets() {
+        let messages = vec![MessageToPublish::new(vec![0; 10]); 5];
+        let max_packet_size = get_max_packet_size(2 * (4 + 10));
+
+        let result =
+            super::split_into_packets("topic", &messages, Some(max_packet_size), PROTOCOL_VERSION)
+                .unwrap();
+
+        assert_eq!(3, result.len());
+        assert_eq!(2, result[0].len());
+        assert_eq!(2, result[1].len());
+        assert_eq!(1, result[2].len());
+    }
+
+    #[test]
+    fn test_single_message_is_too_large() {
+        let messages = vec![
+            MessageToPublish::new(vec![0; 10]),
+            MessageToPublish::new(vec![0; 100]),
+        ];
+        let max_packet_size = get_max_packet_size(4 + 50);
+
+        let result =
+            super::split_into_packets("topic", &messages, Some(max_packet_size), PROTOCOL_VERSION);
+
+        assert!(matches!(result, Err(PublishError::MessageTooLarge { .. })));
+    }
+}
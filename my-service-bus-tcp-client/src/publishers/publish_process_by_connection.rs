@@ -8,20 +8,49 @@ use rust_extensions::TaskCompletion;
 pub struct PublishProcessByConnection {
     pub socket: Arc<MySbTcpConnection>,
     pub requests: HashMap<i64, TaskCompletion<(), PublishError>>,
+    request_sizes: HashMap<i64, usize>,
+    pub bytes_in_flight: usize,
 }
 
 impl PublishProcessByConnection {
     pub fn new(socket: Arc<MySbTcpConnection>) -> Self {
         Self {
             requests: HashMap::new(),
+            request_sizes: HashMap::new(),
+            bytes_in_flight: 0,
             socket,
         }
     }
+
+    pub fn add_request(
+        &mut self,
+        request_id: i64,
+        task: TaskCompletion<(), PublishError>,
+        payload_size: usize,
+    ) {
+        self.requests.insert(request_id, task);
+        self.request_sizes.insert(request_id, payload_size);
+        self.bytes_in_flight += payload_size;
+    }
+
+    pub fn remove_request(&mut self, request_id: i64) -> Option<TaskCompletion<(), PublishError>> {
+        if let Some(payload_size) = self.request_sizes.remove(&request_id) {
+            self.bytes_in_flight -= payload_size;
+        }
+
+        self.requests.remove(&request_id)
+    }
+
+    pub fn remove_all_requests(&mut self) -> Vec<TaskCompletion<(), PublishError>> {
+        self.request_sizes.clear();
+        self.bytes_in_flight = 0;
+        self.requests.drain().map(|(_, task)| task).collect()
+    }
 }
 
 impl Drop for PublishProcessByConnection {
     fn drop(&mut self) {
-        for (_, mut task) in self.requests.drain() {
+        for mut task in self.remove_all_requests() {
             task.set_error(PublishError::Disconnected);
         }
     }
@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy)]
+pub enum RejectPolicy {
+    StopReconnecting,
+    BackOff(Duration),
+}
+
+impl Default for RejectPolicy {
+    fn default() -> Self {
+        Self::BackOff(Duration::from_secs(5))
+    }
+}
+
+struct LastReject {
+    reason: String,
+    reconnect_after: Option<Instant>,
+}
+
+pub struct MySbRejects {
+    policy: RejectPolicy,
+    last_reject: Mutex<Option<LastReject>>,
+}
+
+impl MySbRejects {
+    pub fn new(policy: RejectPolicy) -> Self {
+        Self {
+            policy,
+            last_reject: Mutex::new(None),
+        }
+    }
+
+    pub fn get_policy(&self) -> RejectPolicy {
+        self.policy
+    }
+
+    pub fn rejected(&self, reason: String) {
+        let reconnect_after = match self.policy {
+            RejectPolicy::StopReconnecting => None,
+            RejectPolicy::BackOff(back_off) => Some(Instant::now() + back_off),
+        };
+
+        let mut write_access = self.last_reject.lock();
+        *write_access = Some(LastReject {
+            reason,
+            reconnect_after,
+        });
+    }
+
+    pub fn get_reject_reason(&self) -> Option<String> {
+        let read_access = self.last_reject.lock();
+        read_access.as_ref().map(|itm| itm.reason.clone())
+    }
+
+    pub fn reconnect_is_stopped(&self) -> bool {
+        if let RejectPolicy::StopReconnecting = self.policy {
+            return self.get_reject_reason().is_some();
+        }
+
+        false
+    }
+
+    // Returns false if we must not reconnect anymore
+    pub async fn wait_before_reconnect(&self) -> bool {
+        if self.reconnect_is_stopped() {
+            return false;
+        }
+
+        let reconnect_after = {
+            let read_access = self.last_reject.lock();
+            read_access.as_ref().and_then(|itm| itm.reconnect_after)
+        };
+
+        if let Some(reconnect_after) = reconnect_after {
+            tokio::time::sleep_until(reconnect_after).await;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{MySbRejects, RejectPolicy};
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stop_reconnecting_does_not_reconnect() {
+        let rejects = MySbRejects::new(RejectPolicy::StopReconnecting);
+
+        assert!(rejects.wait_before_reconnect().await);
+
+        rejects.rejected("test reason".to_string());
+
+        assert!(rejects.reconnect_is_stopped());
+        assert!(!rejects.wait_before_reconnect().await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_back_off_delays_reconnect() {
+        let rejects = MySbRejects::new(RejectPolicy::BackOff(Duration::from_secs(5)));
+
+        rejects.rejected("test reason".to_string());
+
+        let started = Instant::now();
+
+        assert!(!rejects.reconnect_is_stopped());
+        assert!(rejects.wait_before_reconnect().await);
+        assert!(started.elapsed() >= Duration::from_secs(5));
+    }
+}
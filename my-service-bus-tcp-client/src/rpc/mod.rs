@@ -0,0 +1,8 @@
+mod rpc_client;
+mod rpc_server;
+
+pub use rpc_client::*;
+pub use rpc_server::*;
+
+pub const RPC_CORRELATION_ID_HEADER: &str = "correlation-id";
+pub const RPC_REPLY_TO_HEADER: &str = "reply-to";
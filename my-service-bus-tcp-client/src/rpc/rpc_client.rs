@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use my_service_bus_abstractions::{
+    publisher::{MessageToPublish, MyServiceBusPublisher},
+    subscriber::{
+        MessagesReader, MySbMessageDeserializer, MySbSubscriberHandleError, SubscriberCallback,
+    },
+    MySbMessageSerializer, PublishError, SbMessageHeaders,
+};
+use parking_lot::Mutex;
+use rust_extensions::date_time::DateTimeAsMicroseconds;
+use tokio::sync::{broadcast, oneshot};
+
+use crate::{MySbConnectionEvent, RpcError};
+
+use super::{RPC_CORRELATION_ID_HEADER, RPC_REPLY_TO_HEADER};
+
+pub struct RpcPendingCalls<TResp> {
+    reply_queue_id: String,
+    // Start time of this instance, so a restarted client with the same reply queue
+    // does not take replies to the requests of the previous one
+    instance_id: i64,
+    next_id: AtomicU64,
+    calls: Mutex<HashMap<String, oneshot::Sender<TResp>>>,
+}
+
+impl<TResp> RpcPendingCalls<TResp> {
+    pub fn new(reply_queue_id: String) -> Self {
+        Self {
+            reply_queue_id,
+            instance_id: DateTimeAsMicroseconds::now().unix_microseconds,
+            next_id: AtomicU64::new(0),
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get_next_correlation_id(&self) -> String {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        format!("{}-{}-{}", self.reply_queue_id, self.instance_id, id)
+    }
+
+    fn add(&self, correlation_id: String) -> oneshot::Receiver<TResp> {
+        let (sender, receiver) = oneshot::channel();
+        let mut write_access = self.calls.lock();
+        write_access.insert(correlation_id, sender);
+        receiver
+    }
+
+    fn remove(&self, correlation_id: &str) {
+        let mut write_access = self.calls.lock();
+        write_access.remove(correlation_id);
+    }
+
+    fn set_response(&self, correlation_id: &str, response: TResp) {
+        let sender = {
+            let mut write_access = self.calls.lock();
+            write_access.remove(correlation_id)
+        };
+
+        if let Some(sender) = sender {
+            let _ = sender.send(response);
+        }
+    }
+
+    // Dropped senders fail the awaiting calls with RpcError::Disconnected
+    fn disconnected(&self) {
+        let mut write_access = self.calls.lock();
+        write_access.clear();
+    }
+
+    pub fn get_pending_calls(&self) -> usize {
+        let read_access = self.calls.lock();
+        read_access.len()
+    }
+}
+
+#[async_trait::async_trait]
+impl<TResp: MySbMessageDeserializer<Item = TResp> + Send + Sync + 'static> SubscriberCallback<TResp>
+    for RpcPendingCalls<TResp>
+{
+    async fn handle_messages(
+        &self,
+        messages_reader: &MessagesReader<TResp>,
+    ) -> Result<(), MySbSubscriberHandleError> {
+        while let Some(mut message) = messages_reader.get_next_message().await {
+            if message.headers.get(RPC_REPLY_TO_HEADER) != Some(self.reply_queue_id.as_str()) {
+                continue;
+            }
+
+            let correlation_id = match message.headers.get(RPC_CORRELATION_ID_HEADER) {
+                Some(correlation_id) => correlation_id.to_string(),
+                None => continue,
+            };
+
+            self.set_response(correlation_id.as_str(), message.take_message());
+        }
+
+        Ok(())
+    }
+}
+
+pub struct RpcClient<TReq: MySbMessageSerializer, TResp> {
+    publisher: MyServiceBusPublisher<TReq>,
+    pending_calls: Arc<RpcPendingCalls<TResp>>,
+    timeout: Duration,
+}
+
+impl<TReq: MySbMessageSerializer, TResp: Send + Sync + 'static> RpcClient<TReq, TResp> {
+    pub fn new(
+        publisher: MyServiceBusPublisher<TReq>,
+        pending_calls: Arc<RpcPendingCalls<TResp>>,
+        timeout: Duration,
+        connection_events: broadcast::Receiver<MySbConnectionEvent>,
+    ) -> Self {
+        tokio::spawn(clean_up_on_disconnect(
+            Arc::downgrade(&pending_calls),
+            connection_events,
+        ));
+
+        Self {
+            publisher,
+            pending_calls,
+            timeout,
+        }
+    }
+
+    pub async fn call(&self, request: &TReq) -> Result<TResp, RpcError> {
+        let correlation_id = self.pending_calls.get_next_correlation_id();
+
+        let headers = SbMessageHeaders::new()
+            .add(RPC_CORRELATION_ID_HEADER, correlation_id.as_str())
+            .add(
+                RPC_REPLY_TO_HEADER,
+                self.pending_calls.reply_queue_id.as_str(),
+            );
+
+        let (content, headers) = request
+            .serialize(Some(headers))
+            .map_err(|err| RpcError::PublishError(PublishError::SerializationError(err)))?;
+
+        let response = self.pending_calls.add(correlation_id.to_string());
+
+        let result = self
+            .publisher
+            .client
+            .publish_message_with_options(
+                &self.publisher.topic_id,
+                MessageToPublish { headers, content },
+                self.publisher.do_retries,
+                self.publisher.persist_immediately,
+            )
+            .await;
+
+        if let Err(err) = result {
+            self.pending_calls.remove(correlation_id.as_str());
+            return Err(RpcError::PublishError(err));
+        }
+
+        match tokio::time::timeout(self.timeout, response).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(RpcError::Disconnected),
+            Err(_) => {
+                self.pending_calls.remove(correlation_id.as_str());
+                Err(RpcError::Timeout)
+            }
+        }
+    }
+
+    pub fn get_pending_calls(&self) -> usize {
+        self.pending_calls.get_pending_calls()
+    }
+}
+
+async fn clean_up_on_disconnect<TResp>(
+    pending_calls: Weak<RpcPendingCalls<TResp>>,
+    mut connection_events: broadcast::Receiver<MySbConnectionEvent>,
+) {
+    loop {
+        let event = match connection_events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        let pending_calls = match pending_calls.upgrade() {
+            Some(pending_calls) => pending_calls,
+            None => return,
+        };
+
+        if let MySbConnectionEvent::Disconnected { .. } = event {
+            pending_calls.disconnected();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RpcPendingCalls;
+
+    #[tokio::test]
+    async fn test_response_is_delivered_by_correlation_id() {
+        let pending_calls = RpcPendingCalls::<String>::new("reply-queue".to_string());
+
+        let correlation_id = pending_calls.get_next_correlation_id();
+        let response = pending_calls.add(correlation_id.to_string());
+
+        pending_calls.set_response("unknown", "other".to_string());
+        pending_calls.set_response(correlation_id.as_str(), "response".to_string());
+
+        assert_eq!("response", response.await.unwrap());
+        assert_eq!(0, pending_calls.get_pending_calls());
+    }
+
+    #[tokio::test]
+    async fn test_pending_calls_are_failed_on_disconnect() {
+        let pending_calls = RpcPendingCalls::<String>::new("reply-queue".to_string());
+
+        let response = pending_calls.add(pending_calls.get_next_correlation_id());
+        pending_calls.disconnected();
+
+        assert!(response.await.is_err());
+        assert_eq!(0, pending_calls.get_pending_calls());
+    }
+
+    #[tokio::test]
+    async fn test_correlation_ids_are_unique_per_instance() {
+        let first = RpcPendingCalls::<String>::new("reply-queue".to_string());
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let second = RpcPendingCalls::<String>::new("reply-queue".to_string());
+
+        assert_ne!(
+            first.get_next_correlation_id(),
+            second.get_next_correlation_id()
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use std::{collections::HashMap, sync::Arc};
+
+use my_service_bus_abstractions::{
+    publisher::{MessageToPublish, MyServiceBusPublisher},
+    subscriber::{
+        MessagesReader, MySbMessageDeserializer, MySbSubscriberHandleError, SubscriberCallback,
+    },
+    MySbMessageSerializer, SbMessageHeaders,
+};
+use rust_extensions::Logger;
+
+use super::{RPC_CORRELATION_ID_HEADER, RPC_REPLY_TO_HEADER};
+
+// A request whose response can not be published is redelivered and handled again,
+// so handlers must be idempotent.
+#[async_trait::async_trait]
+pub trait RpcHandler<TReq, TResp> {
+    async fn handle(&self, request: TReq) -> TResp;
+}
+
+pub struct RpcServer<TReq, TResp: MySbMessageSerializer> {
+    publisher: MyServiceBusPublisher<TResp>,
+    handler: Arc<dyn RpcHandler<TReq, TResp> + Send + Sync + 'static>,
+    logger: Arc<dyn Logger + Send + Sync + 'static>,
+}
+
+impl<TReq, TResp: MySbMessageSerializer> RpcServer<TReq, TResp> {
+    pub fn new(
+        publisher: MyServiceBusPublisher<TResp>,
+        handler: Arc<dyn RpcHandler<TReq, TResp> + Send + Sync + 'static>,
+        logger: Arc<dyn Logger + Send + Sync + 'static>,
+    ) -> Self {
+        Self {
+            publisher,
+            handler,
+            logger,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<
+        TReq: MySbMessageDeserializer<Item = TReq> + Send + Sync + 'static,
+        TResp: MySbMessageSerializer + Send + Sync + 'static,
+    > SubscriberCallback<TReq> for RpcServer<TReq, TResp>
+{
+    async fn handle_messages(
+        &self,
+        messages_reader: &MessagesReader<TReq>,
+    ) -> Result<(), MySbSubscriberHandleError> {
+        while let Some(mut message) = messages_reader.get_next_message().await {
+            let correlation_id = message.headers.get(RPC_CORRELATION_ID_HEADER);
+            let reply_to = message.headers.get(RPC_REPLY_TO_HEADER);
+
+            let (correlation_id, reply_to) = match (correlation_id, reply_to) {
+                (Some(correlation_id), Some(reply_to)) => {
+                    (correlation_id.to_string(), reply_to.to_string())
+                }
+                _ => {
+                    let mut ctx = HashMap::new();
+                    ctx.insert(
+                        "topicId".to_string(),
+                        messages_reader.data.topic_id.as_str().to_string(),
+                    );
+                    ctx.insert("messageId".to_string(), message.id.get_value().to_string());
+                    self.logger.write_warning(
+                        "RpcServer".to_string(),
+                        "Request has no correlation-id or reply-to header. Skipping it".to_string(),
+                        Some(ctx),
+                    );
+                    continue;
+                }
+            };
+
+            let response = self.handler.handle(message.take_message()).await;
+
+            let headers = SbMessageHeaders::new()
+                .add(RPC_CORRELATION_ID_HEADER, correlation_id.as_str())
+                .add(RPC_REPLY_TO_HEADER, reply_to.as_str());
+
+            let (content, headers) = response
+                .serialize(Some(headers))
+                .map_err(MySbSubscriberHandleError::Other)?;
+
+            let result = self
+                .publisher
+                .client
+                .publish_message_with_options(
+                    &self.publisher.topic_id,
+                    MessageToPublish { headers, content },
+                    self.publisher.do_retries,
+                    self.publisher.persist_immediately,
+                )
+                .await;
+
+            // Only this request is redelivered, the other ones of the batch are confirmed
+            if let Err(err) = result {
+                message.mark_as_not_delivered().await;
+
+                let mut ctx = HashMap::new();
+                ctx.insert(
+                    "topicId".to_string(),
+                    messages_reader.data.topic_id.as_str().to_string(),
+                );
+                ctx.insert("correlationId".to_string(), correlation_id);
+                self.logger.write_error(
+                    "RpcServer".to_string(),
+                    format!("Can not publish response: {:?}", err),
+                    Some(ctx),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
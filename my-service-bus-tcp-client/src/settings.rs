@@ -1,4 +1,22 @@
+use crate::{FailoverPolicy, MySbTlsSettings, RejectPolicy};
+
 #[async_trait::async_trait]
 pub trait MyServiceBusSettings {
     async fn get_host_port(&self) -> String;
+
+    async fn get_host_ports(&self) -> Vec<String> {
+        vec![self.get_host_port().await]
+    }
+
+    fn get_failover_policy(&self) -> FailoverPolicy {
+        FailoverPolicy::Priority
+    }
+
+    fn get_reject_policy(&self) -> RejectPolicy {
+        RejectPolicy::default()
+    }
+
+    async fn get_tls_settings(&self) -> Option<MySbTlsSettings> {
+        None
+    }
 }
@@ -1,5 +1,7 @@
 mod my_sb_subscribers;
 mod my_sb_subscribers_data;
+mod my_sb_subscription;
 
 pub use my_sb_subscribers::MySbSubscribers;
 pub use my_sb_subscribers_data::MySbSubscribersData;
+pub use my_sb_subscription::MySbSubscription;
@@ -1,23 +1,40 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use my_service_bus_abstractions::{
-    MySbMessage, MyServiceBusSubscriberClient, MyServiceBusSubscriberClientCallback,
+    queue_with_intervals::QueueIndexRange, MySbMessage, MyServiceBusSubscriberClient,
+    MyServiceBusSubscriberClientCallback,
 };
 
 use my_service_bus_tcp_shared::{MySbTcpConnection, MySbTcpContract};
 use parking_lot::Mutex;
+use rust_extensions::Logger;
 
+use crate::{
+    ConfirmationType, MySbMetrics, SubscribeError, UnexpectedDelivery, UnexpectedDeliveryHandler,
+};
 
-use super::MySbSubscribersData;
+use super::{MySbSubscribersData, MySbSubscription};
 
 pub struct MySbSubscribers {
     subscribers: Arc<Mutex<MySbSubscribersData>>,
+    metrics: Arc<MySbMetrics>,
+    logger: Arc<dyn Logger + Send + Sync + 'static>,
+    unexpected_delivery_handler: Option<Arc<dyn UnexpectedDeliveryHandler + Send + Sync + 'static>>,
 }
 
 impl MySbSubscribers {
-    pub fn new() -> Self {
+    pub fn new(
+        metrics: Arc<MySbMetrics>,
+        logger: Arc<dyn Logger + Send + Sync + 'static>,
+        unexpected_delivery_handler: Option<
+            Arc<dyn UnexpectedDeliveryHandler + Send + Sync + 'static>,
+        >,
+    ) -> Self {
         Self {
             subscribers: Arc::new(Mutex::new(MySbSubscribersData::new())),
+            metrics,
+            logger,
+            unexpected_delivery_handler,
         }
     }
 
@@ -26,9 +43,66 @@ impl MySbSubscribers {
         topic_id: &'static str,
         queue_id: String,
         callback: Arc<dyn MyServiceBusSubscriberClientCallback + Send + Sync + 'static>,
-    ) {
+    ) -> Result<MySbSubscription, SubscribeError> {
+        let (acknowledged, connection) = {
+            let mut write_access = self.subscribers.lock();
+            let acknowledged =
+                write_access.add(topic_id, queue_id.to_string(), callback.clone())?;
+            (acknowledged, write_access.connection.clone())
+        };
+
+        if let Some(connection) = connection {
+            self.send_subscribe(&connection, callback.as_ref());
+        }
+
+        Ok(MySbSubscription::new(
+            topic_id.to_string(),
+            queue_id,
+            acknowledged,
+        ))
+    }
+
+    pub fn replace(
+        &self,
+        topic_id: &'static str,
+        queue_id: String,
+        callback: Arc<dyn MyServiceBusSubscriberClientCallback + Send + Sync + 'static>,
+    ) -> MySbSubscription {
+        let (acknowledged, is_new, connection) = {
+            let mut write_access = self.subscribers.lock();
+            let (acknowledged, is_new) =
+                write_access.replace(topic_id, queue_id.to_string(), callback.clone());
+            (acknowledged, is_new, write_access.connection.clone())
+        };
+
+        if is_new {
+            if let Some(connection) = connection {
+                self.send_subscribe(&connection, callback.as_ref());
+            }
+        }
+
+        MySbSubscription::new(topic_id.to_string(), queue_id, acknowledged)
+    }
+
+    pub fn remove(&self, topic_id: &str, queue_id: &str) -> bool {
         let mut write_access = self.subscribers.lock();
-        write_access.add(topic_id, queue_id, callback);
+        write_access.remove(topic_id, queue_id).is_some()
+    }
+
+    pub fn recycle_connection(&self) {
+        let connection = {
+            let read_access = self.subscribers.lock();
+            read_access.connection.clone()
+        };
+
+        if let Some(connection) = connection {
+            connection.disconnect();
+        }
+    }
+
+    pub fn subscribe_acknowledged(&self, topic_id: &str, queue_id: &str) {
+        let read_access = self.subscribers.lock();
+        read_access.set_acknowledged(topic_id, queue_id);
     }
 
     pub async fn new_messages(
@@ -39,74 +113,225 @@ impl MySbSubscribers {
         connection_id: i32,
         messages: Vec<MySbMessage>,
     ) {
-        let callback = {
-            let read_access = self.subscribers.lock();
-            read_access.get_callback(topic_id.as_str(), queue_id.as_str())
+        let (callback, is_unsubscribed) = {
+            let mut write_access = self.subscribers.lock();
+
+            if write_access.is_shutting_down {
+                return;
+            }
+
+            let callback = write_access.get_callback(topic_id.as_str(), queue_id.as_str());
+
+            if callback.is_some() {
+                write_access.deliveries_in_flight.insert((
+                    topic_id.to_string(),
+                    queue_id.to_string(),
+                    confirmation_id,
+                ));
+            }
+
+            let is_unsubscribed = write_access
+                .unsubscribed
+                .contains(&(topic_id.to_string(), queue_id.to_string()));
+
+            (callback, is_unsubscribed)
         };
 
-        if let Some(callback) = callback {
-            callback
-                .new_events(messages, confirmation_id, connection_id).await;
-        }
+        let callback = match callback {
+            Some(callback) => callback,
+            None => {
+                // Expected until the session is recycled after unsubscribe
+                if is_unsubscribed {
+                    self.confirm_delivery(
+                        topic_id.as_str(),
+                        queue_id.as_str(),
+                        confirmation_id,
+                        connection_id,
+                        false,
+                    );
+                    return;
+                }
+
+                self.unexpected_delivery(UnexpectedDelivery {
+                    topic_id,
+                    queue_id,
+                    confirmation_id,
+                    connection_id,
+                    messages,
+                });
+                return;
+            }
+        };
+
+        let redelivered = messages.iter().filter(|itm| itm.attempt_no > 0).count();
+        self.metrics.batch_delivered(
+            topic_id.as_str(),
+            queue_id.as_str(),
+            messages.len() as u64,
+            redelivered as u64,
+        );
+
+        callback
+            .new_events(messages, confirmation_id, connection_id)
+            .await;
     }
 
-     fn get_subscribers(
+    // Ignored messages of a batch are confirmed with an intermediary confirmation,
+    // so a failed handler does not bring them back
+    pub fn messages_are_ignored(
         &self,
-    ) -> Vec<Arc<dyn MyServiceBusSubscriberClientCallback + Send + Sync + 'static>> {
-        let mut result = Vec::new();
-        let read_access = self.subscribers.lock();
-
-        for subscribers in read_access.subscribers.values() {
-            for subscriber in subscribers.values() {
-                result.push(subscriber.clone());
-            }
+        topic_id: &str,
+        queue_id: &str,
+        confirmation_id: i64,
+        connection_id: i32,
+        ignored: Vec<i64>,
+        all_messages_are_ignored: bool,
+    ) {
+        if all_messages_are_ignored {
+            self.confirm_delivery(topic_id, queue_id, confirmation_id, connection_id, true);
+            return;
         }
 
-        result
+        let delivered = ignored
+            .into_iter()
+            .map(|id| QueueIndexRange {
+                from_id: id,
+                to_id: id,
+            })
+            .collect();
+
+        self.intermediary_confirm(
+            topic_id,
+            queue_id,
+            confirmation_id,
+            connection_id,
+            delivered,
+        );
     }
 
-    pub  fn new_connection(&self, connection: Arc<MySbTcpConnection>) {
-        {
-            let mut write_access = self.subscribers.lock();
-            write_access.connection = Some(connection.clone());
-        }
+    // Otherwise the broker keeps the messages in flight until the connection is dropped
+    fn unexpected_delivery(&self, delivery: UnexpectedDelivery) {
+        let mut ctx = HashMap::new();
+        ctx.insert("topicId".to_string(), delivery.topic_id.to_string());
+        ctx.insert("queueId".to_string(), delivery.queue_id.to_string());
+        ctx.insert(
+            "confirmationId".to_string(),
+            delivery.confirmation_id.to_string(),
+        );
 
-        for subscriber in self.get_subscribers() {
-            let packet = MySbTcpContract::Subscribe {
-                topic_id: subscriber.get_topic_id().to_string(),
-                queue_id: subscriber.get_queue_id().to_string(),
-                queue_type: subscriber.get_queue_type(),
-            };
+        self.logger.write_warning(
+            "new_messages".to_string(),
+            format!(
+                "{} messages are delivered to a queue without subscriber. Confirmed as failed",
+                delivery.messages.len()
+            ),
+            Some(ctx),
+        );
 
-            println!(
-                "Sending Subscribe packet: topic_id={}, queue_id={}, queue_type={:?}",
-                subscriber.get_topic_id(),
-                subscriber.get_queue_id(),
-                subscriber.get_queue_type()
-            );
+        self.metrics
+            .unexpected_delivery(delivery.topic_id.as_str(), delivery.queue_id.as_str());
+
+        self.confirm_delivery(
+            delivery.topic_id.as_str(),
+            delivery.queue_id.as_str(),
+            delivery.confirmation_id,
+            delivery.connection_id,
+            false,
+        );
+
+        if let Some(handler) = self.unexpected_delivery_handler.as_ref() {
+            handler.unexpected_delivery(&delivery);
+        }
+    }
+
+    pub fn new_connection(&self, connection: Arc<MySbTcpConnection>) {
+        let subscribers = {
+            let mut write_access = self.subscribers.lock();
+            write_access.connection = Some(connection.clone());
+            write_access.unsubscribed.clear();
+            write_access.get_subscribers()
+        };
 
-            connection.send(&packet);
+        for subscriber in subscribers {
+            self.send_subscribe(&connection, subscriber.as_ref());
         }
     }
     pub fn disconnect(&self) {
         let mut write_access = self.subscribers.lock();
         write_access.connection = None;
+        write_access.deliveries_in_flight.clear();
+        write_access.reset_acknowledgements();
+    }
+
+    pub fn shutdown(&self) {
+        let mut write_access = self.subscribers.lock();
+        write_access.is_shutting_down = true;
+    }
+
+    pub fn get_deliveries_in_flight(&self) -> usize {
+        let read_access = self.subscribers.lock();
+        read_access.deliveries_in_flight.len()
+    }
+
+    pub fn get_queue_deliveries_in_flight(&self, topic_id: &str, queue_id: &str) -> usize {
+        let read_access = self.subscribers.lock();
+        read_access
+            .deliveries_in_flight
+            .iter()
+            .filter(|(topic, queue, _)| topic == topic_id && queue == queue_id)
+            .count()
+    }
+
+    fn delivery_is_confirmed(&self, topic_id: &str, queue_id: &str, confirmation_id: i64) {
+        let mut write_access = self.subscribers.lock();
+        write_access.deliveries_in_flight.remove(&(
+            topic_id.to_string(),
+            queue_id.to_string(),
+            confirmation_id,
+        ));
+    }
+
+    fn send_subscribe(
+        &self,
+        connection: &MySbTcpConnection,
+        subscriber: &(dyn MyServiceBusSubscriberClientCallback + Send + Sync + 'static),
+    ) {
+        let packet = MySbTcpContract::Subscribe {
+            topic_id: subscriber.get_topic_id().to_string(),
+            queue_id: subscriber.get_queue_id().to_string(),
+            queue_type: subscriber.get_queue_type(),
+        };
+
+        let mut ctx = HashMap::new();
+        ctx.insert("topicId".to_string(), subscriber.get_topic_id().to_string());
+        ctx.insert("queueId".to_string(), subscriber.get_queue_id().to_string());
+        ctx.insert(
+            "queueType".to_string(),
+            format!("{:?}", subscriber.get_queue_type()),
+        );
+
+        self.logger.write_info(
+            "send_subscribe".to_string(),
+            "Sending Subscribe packet".to_string(),
+            Some(ctx),
+        );
+
+        connection.send(&packet);
     }
 
     fn send_packet(&self, mut tcp_contract: MySbTcpContract, connection_id: i32) {
         let subscribers = self.subscribers.clone();
 
+        let connection = {
+            let access = subscribers.lock();
+            access.connection.clone()
+        };
 
-            let connection = {
-                let access = subscribers.lock();
-                access.connection.clone()
-            };
-
-            if let Some(connection) = connection {
-                if connection.id == connection_id {
-                    connection.send(&mut tcp_contract);
-                }
+        if let Some(connection) = connection {
+            if connection.id == connection_id {
+                connection.send(&mut tcp_contract);
             }
+        }
     }
 }
 
@@ -119,20 +344,25 @@ impl MyServiceBusSubscriberClient for MySbSubscribers {
         connection_id: i32,
         delivered: bool,
     ) {
-        let tcp_contract = if delivered {
-            MySbTcpContract::NewMessagesConfirmation {
+        let (tcp_contract, confirmation_type) = if delivered {
+            let tcp_contract = MySbTcpContract::NewMessagesConfirmation {
                 topic_id: topic_id.to_string(),
                 queue_id: queue_id.to_string(),
                 confirmation_id,
-            }
+            };
+            (tcp_contract, ConfirmationType::AllOk)
         } else {
-            MySbTcpContract::AllMessagesConfirmedAsFail {
+            let tcp_contract = MySbTcpContract::AllMessagesConfirmedAsFail {
                 topic_id: topic_id.to_string(),
                 queue_id: queue_id.to_string(),
                 confirmation_id,
-            }
+            };
+            (tcp_contract, ConfirmationType::AllFail)
         };
 
+        self.metrics
+            .confirmation_sent(topic_id, queue_id, confirmation_type);
+        self.delivery_is_confirmed(topic_id, queue_id, confirmation_id);
         self.send_packet(tcp_contract, connection_id);
     }
 
@@ -152,6 +382,9 @@ impl MyServiceBusSubscriberClient for MySbSubscribers {
             delivered,
         };
 
+        self.metrics
+            .confirmation_sent(topic_id, queue_id, ConfirmationType::Partial);
+        self.delivery_is_confirmed(topic_id, queue_id, confirmation_id);
         self.send_packet(tcp_contract, connection_id);
     }
 
@@ -171,6 +404,145 @@ impl MyServiceBusSubscriberClient for MySbSubscribers {
             delivered,
         };
 
+        self.metrics
+            .confirmation_sent(topic_id, queue_id, ConfirmationType::Intermediary);
         self.send_packet(tcp_contract, connection_id);
     }
+
+    fn deserialization_failed(&self, topic_id: &str, queue_id: &str, messages_amount: usize) {
+        self.metrics
+            .deserialization_failed(topic_id, queue_id, messages_amount as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use my_service_bus_abstractions::{
+        subscriber::TopicQueueType, MySbMessage, MyServiceBusSubscriberClientCallback,
+        SbMessageHeaders,
+    };
+    use parking_lot::Mutex;
+
+    use super::MySbSubscribers;
+    use crate::{
+        test_utils::TestLogger, MySbMetrics, UnexpectedDelivery, UnexpectedDeliveryHandler,
+    };
+
+    struct TestHandler(Mutex<Vec<(String, String, i64, usize)>>);
+
+    impl UnexpectedDeliveryHandler for TestHandler {
+        fn unexpected_delivery(&self, delivery: &UnexpectedDelivery) {
+            self.0.lock().push((
+                delivery.topic_id.to_string(),
+                delivery.queue_id.to_string(),
+                delivery.confirmation_id,
+                delivery.messages.len(),
+            ));
+        }
+    }
+
+    struct TestCallback;
+
+    #[async_trait::async_trait]
+    impl MyServiceBusSubscriberClientCallback for TestCallback {
+        fn get_topic_id(&self) -> &str {
+            "topic"
+        }
+
+        fn get_queue_id(&self) -> &str {
+            "queue"
+        }
+
+        fn get_queue_type(&self) -> TopicQueueType {
+            TopicQueueType::DeleteOnDisconnect
+        }
+
+        async fn new_events(&self, _: Vec<MySbMessage>, _: i64, _: i32) {}
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_delivery_is_confirmed_as_fail() {
+        let metrics = Arc::new(MySbMetrics::new());
+        let handler = Arc::new(TestHandler(Mutex::new(Vec::new())));
+
+        let subscribers =
+            MySbSubscribers::new(metrics.clone(), Arc::new(TestLogger), Some(handler.clone()));
+
+        let message = MySbMessage {
+            id: 1.into(),
+            attempt_no: 0,
+            headers: SbMessageHeaders::new(),
+            content: vec![],
+        };
+
+        subscribers
+            .new_messages(
+                "topic".to_string(),
+                "queue".to_string(),
+                5,
+                1,
+                vec![message],
+            )
+            .await;
+
+        assert_eq!(
+            vec![("topic".to_string(), "queue".to_string(), 5, 1)],
+            *handler.0.lock()
+        );
+
+        let snapshot = metrics.get_snapshot(0, Default::default());
+        let queue_metrics = snapshot
+            .queues
+            .get(&("topic".to_string(), "queue".to_string()))
+            .unwrap();
+
+        assert_eq!(1, queue_metrics.unexpected_deliveries);
+        assert_eq!(1, queue_metrics.confirmations_all_fail);
+        assert_eq!(0, subscribers.get_deliveries_in_flight());
+    }
+
+    #[tokio::test]
+    async fn test_delivery_to_unsubscribed_queue_is_not_unexpected() {
+        let metrics = Arc::new(MySbMetrics::new());
+        let handler = Arc::new(TestHandler(Mutex::new(Vec::new())));
+
+        let subscribers =
+            MySbSubscribers::new(metrics.clone(), Arc::new(TestLogger), Some(handler.clone()));
+
+        subscribers
+            .add("topic", "queue".to_string(), Arc::new(TestCallback), false)
+            .unwrap();
+
+        assert!(subscribers.remove("topic", "queue"));
+
+        let message = MySbMessage {
+            id: 1.into(),
+            attempt_no: 0,
+            headers: SbMessageHeaders::new(),
+            content: vec![],
+        };
+
+        subscribers
+            .new_messages(
+                "topic".to_string(),
+                "queue".to_string(),
+                5,
+                1,
+                vec![message],
+            )
+            .await;
+
+        assert!(handler.0.lock().is_empty());
+
+        let snapshot = metrics.get_snapshot(0, Default::default());
+        let queue_metrics = snapshot
+            .queues
+            .get(&("topic".to_string(), "queue".to_string()))
+            .unwrap();
+
+        assert_eq!(0, queue_metrics.unexpected_deliveries);
+        assert_eq!(1, queue_metrics.confirmations_all_fail);
+    }
 }
@@ -1,8 +1,14 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use my_service_bus_abstractions::MyServiceBusSubscriberClientCallback;
 use my_service_bus_tcp_shared::{MySbSerializerState, MySbTcpContract, MySbTcpSerializer};
 use my_tcp_sockets::tcp_connection::TcpSocketConnection;
+use tokio::sync::watch;
+
+use crate::SubscribeError;
 
 pub struct MySbSubscribersData {
     pub subscribers: HashMap<
@@ -11,6 +17,11 @@ pub struct MySbSubscribersData {
     >,
     pub connection:
         Option<Arc<TcpSocketConnection<MySbTcpContract, MySbTcpSerializer, MySbSerializerState>>>,
+    pub acknowledged: HashMap<(String, String), watch::Sender<bool>>,
+    pub deliveries_in_flight: HashSet<(String, String, i64)>,
+    // Queues removed until the session is recycled. The server still delivers to them.
+    pub unsubscribed: HashSet<(String, String)>,
+    pub is_shutting_down: bool,
 }
 
 impl MySbSubscribersData {
@@ -18,6 +29,10 @@ impl MySbSubscribersData {
         Self {
             subscribers: HashMap::new(),
             connection: None,
+            acknowledged: HashMap::new(),
+            deliveries_in_flight: HashSet::new(),
+            unsubscribed: HashSet::new(),
+            is_shutting_down: false,
         }
     }
 
@@ -26,21 +41,100 @@ impl MySbSubscribersData {
         topic_id: &'static str,
         queue_id: String,
         subscriber_callback: Arc<dyn MyServiceBusSubscriberClientCallback + Sync + Send + 'static>,
-    ) {
-        if !self.subscribers.contains_key(topic_id) {
-            self.subscribers.insert(topic_id, HashMap::new());
+    ) -> Result<watch::Receiver<bool>, SubscribeError> {
+        if self.get_callback(topic_id, queue_id.as_str()).is_some() {
+            return Err(SubscribeError::AlreadyRegistered {
+                topic_id: topic_id.to_string(),
+                queue_id,
+            });
         }
 
-        let by_topic = self.subscribers.get_mut(topic_id).unwrap();
+        Ok(self.insert_new(topic_id, queue_id, subscriber_callback))
+    }
 
-        if by_topic.contains_key(queue_id.as_str()) {
-            panic!(
-                "Subscriber for topic:{} and queue:{} is already registered",
-                topic_id, queue_id
+    // Returns the acknowledgement receiver and whether the subscriber is new.
+    // A replaced subscriber keeps the acknowledgement, since the queue is already subscribed.
+    pub fn replace(
+        &mut self,
+        topic_id: &'static str,
+        queue_id: String,
+        subscriber_callback: Arc<dyn MyServiceBusSubscriberClientCallback + Sync + Send + 'static>,
+    ) -> (watch::Receiver<bool>, bool) {
+        let by_topic = self.subscribers.entry(topic_id).or_default();
+
+        if !by_topic.contains_key(queue_id.as_str()) {
+            return (
+                self.insert_new(topic_id, queue_id, subscriber_callback),
+                true,
             );
         }
 
-        by_topic.insert(queue_id, subscriber_callback);
+        by_topic.insert(queue_id.to_string(), subscriber_callback);
+
+        let acknowledged = self
+            .acknowledged
+            .entry((topic_id.to_string(), queue_id))
+            .or_insert_with(|| watch::channel(false).0)
+            .subscribe();
+
+        (acknowledged, false)
+    }
+
+    fn insert_new(
+        &mut self,
+        topic_id: &'static str,
+        queue_id: String,
+        subscriber_callback: Arc<dyn MyServiceBusSubscriberClientCallback + Sync + Send + 'static>,
+    ) -> watch::Receiver<bool> {
+        self.unsubscribed
+            .remove(&(topic_id.to_string(), queue_id.to_string()));
+
+        let (acknowledged, result) = watch::channel(false);
+        self.acknowledged
+            .insert((topic_id.to_string(), queue_id.to_string()), acknowledged);
+
+        self.subscribers
+            .entry(topic_id)
+            .or_default()
+            .insert(queue_id, subscriber_callback);
+
+        result
+    }
+
+    pub fn remove(
+        &mut self,
+        topic_id: &str,
+        queue_id: &str,
+    ) -> Option<Arc<dyn MyServiceBusSubscriberClientCallback + Sync + Send + 'static>> {
+        let by_topic = self.subscribers.get_mut(topic_id)?;
+        let result = by_topic.remove(queue_id)?;
+
+        if by_topic.is_empty() {
+            self.subscribers.remove(topic_id);
+        }
+
+        self.acknowledged
+            .remove(&(topic_id.to_string(), queue_id.to_string()));
+
+        self.unsubscribed
+            .insert((topic_id.to_string(), queue_id.to_string()));
+
+        Some(result)
+    }
+
+    pub fn set_acknowledged(&self, topic_id: &str, queue_id: &str) {
+        if let Some(acknowledged) = self
+            .acknowledged
+            .get(&(topic_id.to_string(), queue_id.to_string()))
+        {
+            acknowledged.send_replace(true);
+        }
+    }
+
+    pub fn reset_acknowledgements(&self) {
+        for acknowledged in self.acknowledged.values() {
+            acknowledged.send_replace(false);
+        }
     }
 
     pub fn get_callback(
@@ -54,4 +148,18 @@ impl MySbSubscribersData {
 
         return Some(subscriber.clone());
     }
+
+    pub fn get_subscribers(
+        &self,
+    ) -> Vec<Arc<dyn MyServiceBusSubscriberClientCallback + Send + Sync + 'static>> {
+        let mut result = Vec::new();
+
+        for subscribers in self.subscribers.values() {
+            for subscriber in subscribers.values() {
+                result.push(subscriber.clone());
+            }
+        }
+
+        result
+    }
 }
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::SubscribeError;
+
+pub struct MySbSubscription {
+    pub topic_id: String,
+    pub queue_id: String,
+    acknowledged: watch::Receiver<bool>,
+}
+
+impl MySbSubscription {
+    pub fn new(topic_id: String, queue_id: String, acknowledged: watch::Receiver<bool>) -> Self {
+        Self {
+            topic_id,
+            queue_id,
+            acknowledged,
+        }
+    }
+
+    pub fn is_acknowledged(&self) -> bool {
+        *self.acknowledged.borrow()
+    }
+
+    pub async fn wait_until_acknowledged(&self, timeout: Duration) -> Result<(), SubscribeError> {
+        let mut acknowledged = self.acknowledged.clone();
+        let result = tokio::time::timeout(timeout, acknowledged.wait_for(|value| *value)).await;
+
+        match result {
+            Ok(Ok(_)) => Ok(()),
+            // The sender is dropped once the queue is unsubscribed
+            Ok(Err(_)) => Err(SubscribeError::Unsubscribed {
+                topic_id: self.topic_id.to_string(),
+                queue_id: self.queue_id.to_string(),
+            }),
+            Err(_) => Err(SubscribeError::Timeout {
+                topic_id: self.topic_id.to_string(),
+                queue_id: self.queue_id.to_string(),
+            }),
+        }
+    }
+}
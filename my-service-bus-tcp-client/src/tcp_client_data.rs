@@ -1,22 +1,52 @@
-use std::sync::{atomic::AtomicBool, Arc};
+use std::{collections::HashMap, sync::Arc};
 
 use my_service_bus_tcp_shared::{
     MySbSerializerState, MySbTcpConnection, MySbTcpContract, MySbTcpSerializer,
 };
 use rust_extensions::{Logger, StrOrString};
 
-use crate::{publishers::MySbPublishers, subscribers::MySbSubscribers, IgnoreMessage};
+use crate::{
+    publishers::MySbPublishers, subscribers::MySbSubscribers, IgnoredMessages,
+    MySbConnectionEvents, MySbEndpoints, MySbMetrics, MySbRejects,
+};
 
 #[derive(Clone)]
 pub struct TcpClientData {
     pub app_name: StrOrString<'static>,
     pub app_version: StrOrString<'static>,
     pub client_version: String,
+    pub env_info: Option<String>,
+    pub protocol_version: i32,
     pub publishers: Arc<MySbPublishers>,
     pub subscribers: Arc<MySbSubscribers>,
     pub logger: Arc<dyn Logger + Send + Sync + 'static>,
-    pub has_connection: Arc<AtomicBool>,
-    pub ignore_message: Option<IgnoreMessage>,
+    pub connection_events: Arc<MySbConnectionEvents>,
+    pub ignored_messages: Arc<IgnoredMessages>,
+    pub endpoints: Arc<MySbEndpoints>,
+    pub rejects: Arc<MySbRejects>,
+    pub metrics: Arc<MySbMetrics>,
+}
+
+impl TcpClientData {
+    fn rejected(&self, connection: &Arc<MySbTcpConnection>, reason: String) {
+        let mut ctx = HashMap::new();
+        ctx.insert("connectionId".to_string(), connection.id.to_string());
+        ctx.insert(
+            "rejectPolicy".to_string(),
+            format!("{:?}", self.rejects.get_policy()),
+        );
+
+        self.logger.write_error(
+            "payload".to_string(),
+            format!("Connection is rejected by MyServiceBus. Reason: {}", reason),
+            Some(ctx),
+        );
+
+        self.rejects.rejected(reason.clone());
+        self.publishers
+            .rejected(reason.clone(), self.rejects.reconnect_is_stopped());
+        self.connection_events.rejected(connection.id, reason);
+    }
 }
 
 #[async_trait::async_trait]
@@ -24,11 +54,15 @@ impl my_tcp_sockets::SocketEventCallback<MySbTcpContract, MySbTcpSerializer, MyS
     for TcpClientData
 {
     async fn connected(&mut self, connection: Arc<MySbTcpConnection>) {
+        self.endpoints.connected();
+
         super::new_connection_handler::send_greeting(
             &connection,
             self.app_name.as_str(),
             self.app_version.as_str(),
             self.client_version.as_str(),
+            self.env_info.as_deref(),
+            self.protocol_version,
         );
 
         super::new_connection_handler::send_packet_versions(&connection);
@@ -36,30 +70,64 @@ impl my_tcp_sockets::SocketEventCallback<MySbTcpContract, MySbTcpSerializer, MyS
         self.publishers.new_connection(connection.clone());
         self.subscribers.new_connection(connection.clone());
 
-        self.has_connection
-            .store(true, std::sync::atomic::Ordering::SeqCst);
+        self.connection_events.connected(
+            self.endpoints.get_connected_endpoint().unwrap_or_default(),
+            connection.id,
+        );
     }
 
-    async fn disconnected(&mut self, _connection: Arc<MySbTcpConnection>) {
-        self.has_connection
-            .store(false, std::sync::atomic::Ordering::SeqCst);
+    async fn disconnected(&mut self, connection: Arc<MySbTcpConnection>) {
+        self.connection_events.disconnected(connection.id);
+        self.endpoints.disconnected();
         self.publishers.disconnect();
         self.subscribers.disconnect();
     }
 
     async fn payload(&mut self, connection: &Arc<MySbTcpConnection>, contract: MySbTcpContract) {
+        if let MySbTcpContract::Reject { message } = contract {
+            self.rejected(connection, message);
+            return;
+        }
+
+        self.connection_events.packet_received(connection.id);
+
         match contract {
             my_service_bus_tcp_shared::MySbTcpContract::PublishResponse { request_id } => {
                 self.publishers.set_confirmed(request_id);
             }
+            my_service_bus_tcp_shared::MySbTcpContract::SubscribeResponse { topic_id, queue_id } => {
+                self.subscribers.subscribe_acknowledged(&topic_id, &queue_id);
+            }
             my_service_bus_tcp_shared::MySbTcpContract::NewMessages(mut model) => {
-                if let Some(auto_confirm_message) = self.ignore_message.as_ref() {
-                    if auto_confirm_message.topic_id == model.topic_id
-                        && auto_confirm_message.queue_id == model.queue_id
-                    {
-                        model.messages.retain(|itm| {
-                            itm.id.get_value() != auto_confirm_message.message_id.get_value()
-                        });
+                let ignored = self.ignored_messages.filter(
+                    model.topic_id.as_str(),
+                    model.queue_id.as_str(),
+                    &mut model.messages,
+                );
+
+                if ignored.len() > 0 {
+                    let mut ctx = HashMap::new();
+                    ctx.insert("topicId".to_string(), model.topic_id.to_string());
+                    ctx.insert("queueId".to_string(), model.queue_id.to_string());
+                    ctx.insert("messageIds".to_string(), format!("{:?}", ignored));
+
+                    self.logger.write_warning(
+                        "payload".to_string(),
+                        "Ignored messages are confirmed as delivered".to_string(),
+                        Some(ctx),
+                    );
+
+                    self.subscribers.messages_are_ignored(
+                        model.topic_id.as_str(),
+                        model.queue_id.as_str(),
+                        model.confirmation_id,
+                        connection.id,
+                        ignored,
+                        model.messages.len() == 0,
+                    );
+
+                    if model.messages.len() == 0 {
+                        return;
                     }
                 }
 
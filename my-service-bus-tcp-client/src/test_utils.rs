@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use my_service_bus_abstractions::{
+    subscriber::{
+        MessagesReader, MySbMessageDeserializer, MySbSubscriberHandleError, SubscriberCallback,
+    },
+    GetMySbModelTopicId, MySbMessageSerializer, SbMessageHeaders, SubscriberError,
+};
+use rust_extensions::Logger;
+
+use crate::MyServiceBusSettings;
+
+pub struct TestSettings(pub String);
+
+#[async_trait::async_trait]
+impl MyServiceBusSettings for TestSettings {
+    async fn get_host_port(&self) -> String {
+        self.0.clone()
+    }
+}
+
+pub struct TestLogger;
+
+impl Logger for TestLogger {
+    fn write_info(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+    fn write_warning(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+    fn write_error(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+    fn write_fatal_error(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+    fn write_debug_info(&self, _: String, _: String, _: Option<HashMap<String, String>>) {}
+}
+
+pub struct TestModel;
+
+impl GetMySbModelTopicId for TestModel {
+    fn get_topic_id() -> &'static str {
+        "test-topic"
+    }
+}
+
+impl MySbMessageDeserializer for TestModel {
+    type Item = TestModel;
+
+    fn deserialize(_: &[u8], _: &SbMessageHeaders) -> Result<Self::Item, SubscriberError> {
+        Ok(TestModel)
+    }
+}
+
+impl MySbMessageSerializer for TestModel {
+    fn serialize(
+        &self,
+        headers: Option<SbMessageHeaders>,
+    ) -> Result<(Vec<u8>, SbMessageHeaders), String> {
+        Ok((vec![1], headers.unwrap_or_else(SbMessageHeaders::new)))
+    }
+}
+
+pub struct TestCallback;
+
+#[async_trait::async_trait]
+impl SubscriberCallback<TestModel> for TestCallback {
+    async fn handle_messages(
+        &self,
+        _: &MessagesReader<TestModel>,
+    ) -> Result<(), MySbSubscriberHandleError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use my_tcp_sockets::TlsSettings;
+
+#[derive(Debug, Clone)]
+pub struct MySbClientCertificate {
+    pub cert_pem: String,
+    pub private_key_pem: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MySbTlsSettings {
+    pub server_name: String,
+    pub ca_cert_pem: Option<String>,
+    pub client_certificate: Option<MySbClientCertificate>,
+}
+
+impl MySbTlsSettings {
+    pub fn new(server_name: impl Into<String>) -> Self {
+        Self {
+            server_name: server_name.into(),
+            ca_cert_pem: None,
+            client_certificate: None,
+        }
+    }
+
+    pub fn with_ca_cert_pem(mut self, ca_cert_pem: impl Into<String>) -> Self {
+        self.ca_cert_pem = Some(ca_cert_pem.into());
+        self
+    }
+
+    pub fn with_client_certificate(
+        mut self,
+        cert_pem: impl Into<String>,
+        private_key_pem: impl Into<String>,
+    ) -> Self {
+        self.client_certificate = Some(MySbClientCertificate {
+            cert_pem: cert_pem.into(),
+            private_key_pem: private_key_pem.into(),
+        });
+        self
+    }
+}
+
+impl From<MySbTlsSettings> for TlsSettings {
+    fn from(src: MySbTlsSettings) -> Self {
+        let (client_cert_pem, client_private_key_pem) = match src.client_certificate {
+            Some(client_certificate) => (
+                Some(client_certificate.cert_pem),
+                Some(client_certificate.private_key_pem),
+            ),
+            None => (None, None),
+        };
+
+        Self {
+            server_name: src.server_name,
+            ca_cert_pem: src.ca_cert_pem,
+            client_cert_pem,
+            client_private_key_pem,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use my_tcp_sockets::TlsSettings;
+
+    use super::MySbTlsSettings;
+
+    #[test]
+    fn test_pem_values_are_mapped() {
+        let tls_settings: TlsSettings = MySbTlsSettings::new("my-sb.internal")
+            .with_ca_cert_pem("ca")
+            .with_client_certificate("cert", "key")
+            .into();
+
+        assert_eq!("my-sb.internal", tls_settings.server_name);
+        assert_eq!(Some("ca"), tls_settings.ca_cert_pem.as_deref());
+        assert_eq!(Some("cert"), tls_settings.client_cert_pem.as_deref());
+        assert_eq!(Some("key"), tls_settings.client_private_key_pem.as_deref());
+    }
+
+    #[test]
+    fn test_without_client_certificate() {
+        let tls_settings: TlsSettings = MySbTlsSettings::new("my-sb.internal").into();
+
+        assert!(tls_settings.ca_cert_pem.is_none());
+        assert!(tls_settings.client_cert_pem.is_none());
+        assert!(tls_settings.client_private_key_pem.is_none());
+    }
+}
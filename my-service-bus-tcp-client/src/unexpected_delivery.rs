@@ -0,0 +1,16 @@
+use my_service_bus_abstractions::MySbMessage;
+
+// A NewMessages packet for a topic/queue without a registered subscriber.
+// The batch is already confirmed as failed when the handler is called.
+#[derive(Debug)]
+pub struct UnexpectedDelivery {
+    pub topic_id: String,
+    pub queue_id: String,
+    pub confirmation_id: i64,
+    pub connection_id: i32,
+    pub messages: Vec<MySbMessage>,
+}
+
+pub trait UnexpectedDeliveryHandler {
+    fn unexpected_delivery(&self, delivery: &UnexpectedDelivery);
+}
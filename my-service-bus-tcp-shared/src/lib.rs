@@ -19,6 +19,9 @@ pub use tcp_serializer::MySbTcpSerializer;
 mod tcp_protocol_version;
 pub use tcp_protocol_version::*;
 
+#[cfg(any(test, feature = "test-broker"))]
+pub mod test_broker;
+
 pub type MySbTcpConnection = my_tcp_sockets::tcp_connection::TcpSocketConnection<
     MySbTcpContract,
     MySbTcpSerializer,
@@ -534,6 +534,11 @@ impl MySbTcpContract {
         //crate::tcp_serializers::bool::serialize(&mut result, persist_immediately);
     }
 
+    // Size of a publish packet without the messages themselves
+    pub fn get_publish_payload_overhead(topic_id: &str) -> usize {
+        1 + 1 + topic_id.len() + 8 + 4 + 1
+    }
+
     pub fn unwrap_as_message(
         self,
         packet_version: crate::PacketProtVer,
@@ -36,3 +36,47 @@ pub fn serialize_v3(write_buffer: &mut impl TcpWriteBuffer, v: &[MessageToPublis
         //super::byte_array::serialize(data, &item.content);
     }
 }
+
+pub fn get_serialized_size(item: &MessageToPublish, protocol_version: TcpProtocolVersion) -> usize {
+    let content_size = 4 + item.content.len();
+
+    if protocol_version.get_value() < 3 {
+        return content_size;
+    }
+
+    let mut headers_size = 1;
+
+    for (key, value) in item.headers.iter().take(255) {
+        headers_size += 1 + key.len() + 1 + value.len();
+    }
+
+    headers_size + content_size
+}
+
+#[cfg(test)]
+mod test {
+    use my_service_bus_abstractions::{publisher::MessageToPublish, SbMessageHeaders};
+
+    #[test]
+    fn test_serialized_size() {
+        let messages = vec![
+            MessageToPublish::new(vec![1, 2, 3]),
+            MessageToPublish::new_with_headers(
+                vec![4, 5, 6, 7],
+                SbMessageHeaders::new().add("Key1", "Value1"),
+            ),
+        ];
+
+        for protocol_version in [2, 3] {
+            let mut serialized_data = Vec::new();
+            super::serialize(&mut serialized_data, &messages, protocol_version.into());
+
+            let size: usize = messages
+                .iter()
+                .map(|itm| super::get_serialized_size(itm, protocol_version.into()))
+                .sum();
+
+            assert_eq!(serialized_data.len(), 4 + size);
+        }
+    }
+}
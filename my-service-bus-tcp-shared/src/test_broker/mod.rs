@@ -0,0 +1,4 @@
+mod my_sb_test_broker;
+mod test_broker_data;
+pub use my_sb_test_broker::*;
+pub use test_broker_data::*;
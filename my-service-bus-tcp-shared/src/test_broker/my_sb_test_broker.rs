@@ -0,0 +1,257 @@
+use std::sync::Arc;
+
+use my_service_bus_abstractions::MySbMessage;
+use my_tcp_sockets::{socket_reader::SocketReaderTcpStream, TcpSerializerState};
+use parking_lot::Mutex;
+use tokio::{
+    io::{AsyncWriteExt, ReadHalf},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::UnboundedReceiver,
+    task::JoinHandle,
+};
+
+use crate::{MySbSerializerState, MySbTcpContract};
+
+use super::{TestBrokerData, TestBrokerSession};
+
+// Stand-in broker for integration tests. Listens on localhost and keeps everything in memory.
+// Each queue gets one batch at a time; not confirmed messages are redelivered with the next attempt_no.
+pub struct MySbTestBroker {
+    host_port: String,
+    data: Arc<Mutex<TestBrokerData>>,
+    listener: JoinHandle<()>,
+}
+
+impl MySbTestBroker {
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let host_port = listener.local_addr()?.to_string();
+
+        let data = Arc::new(Mutex::new(TestBrokerData::new()));
+
+        let listener = tokio::spawn(accept_connections(listener, data.clone()));
+
+        Ok(Self {
+            host_port,
+            data,
+            listener,
+        })
+    }
+
+    pub fn get_host_port(&self) -> &str {
+        self.host_port.as_str()
+    }
+
+    pub fn get_sessions_amount(&self) -> usize {
+        self.data.lock().sessions.len()
+    }
+
+    pub fn get_published_messages(&self, topic_id: &str) -> Vec<MySbMessage> {
+        let read_access = self.data.lock();
+        match read_access.published.get(topic_id) {
+            Some(messages) => messages.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn topic_exists(&self, topic_id: &str) -> bool {
+        self.data.lock().published.contains_key(topic_id)
+    }
+
+    pub fn get_confirmations(&self) -> Vec<MySbTcpContract> {
+        self.data.lock().confirmations.clone()
+    }
+
+    // Every packet received from the clients except Ping, in order
+    pub fn get_received_packets(&self) -> Vec<MySbTcpContract> {
+        self.data.lock().received.clone()
+    }
+
+    // Publishes are stored, but PublishResponse is held until resume_publish_responses
+    pub fn pause_publish_responses(&self) {
+        self.data.lock().publish_responses_are_paused = true;
+    }
+
+    pub fn resume_publish_responses(&self) {
+        let mut write_access = self.data.lock();
+        write_access.publish_responses_are_paused = false;
+
+        for (session_id, request_id) in std::mem::take(&mut write_access.held_publish_responses) {
+            if let Some(session) = write_access.sessions.get(&session_id) {
+                session.send(&MySbTcpContract::PublishResponse { request_id });
+            }
+        }
+    }
+
+    // Subscribe to the queue is ignored: no SubscribeResponse and no deliveries
+    pub fn set_unknown_queue(&self, topic_id: &str, queue_id: &str) {
+        self.data
+            .lock()
+            .unknown_queues
+            .insert((topic_id.to_string(), queue_id.to_string()));
+    }
+
+    // Sends Reject to all the sessions and drops them, like the server does
+    pub fn reject_sessions(&self, message: &str) {
+        {
+            let read_access = self.data.lock();
+
+            for session in read_access.sessions.values() {
+                session.send(&MySbTcpContract::Reject {
+                    message: message.to_string(),
+                });
+            }
+        }
+
+        self.disconnect_sessions();
+    }
+
+    // Drops all the connections to test reconnects. Messages in flight are redelivered.
+    pub fn disconnect_sessions(&self) {
+        let mut write_access = self.data.lock();
+
+        let session_ids: Vec<i64> = write_access.sessions.keys().cloned().collect();
+
+        for session_id in session_ids {
+            if let Some(session) = write_access.session_disconnected(session_id) {
+                if let Some(reader) = session.reader {
+                    reader.abort();
+                }
+            }
+        }
+    }
+
+    pub fn stop(&self) {
+        self.listener.abort();
+        self.disconnect_sessions();
+    }
+}
+
+impl Drop for MySbTestBroker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn accept_connections(listener: TcpListener, data: Arc<Mutex<TestBrokerData>>) {
+    let mut session_id = 0;
+
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(result) => result,
+            Err(_) => return,
+        };
+
+        let _ = stream.set_nodelay(true);
+
+        session_id += 1;
+
+        let (read_half, mut write_half) = tokio::io::split(stream);
+        let (sender, mut receiver): (_, UnboundedReceiver<Vec<u8>>) =
+            tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Some(payload) = receiver.recv().await {
+                if write_half.write_all(&payload).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut write_access = data.lock();
+
+        write_access.sessions.insert(
+            session_id,
+            TestBrokerSession {
+                sender,
+                serializer_state: MySbSerializerState::new(crate::DEFAULT_TCP_PROTOCOL_VERSION),
+                reader: None,
+            },
+        );
+
+        let reader = tokio::spawn(read_packets(data.clone(), session_id, read_half));
+
+        if let Some(session) = write_access.sessions.get_mut(&session_id) {
+            session.reader = Some(reader);
+        }
+    }
+}
+
+// Packets are read by the same deserializer as the server uses
+async fn read_packets(
+    data: Arc<Mutex<TestBrokerData>>,
+    session_id: i64,
+    read_half: ReadHalf<TcpStream>,
+) {
+    let mut socket_reader = SocketReaderTcpStream::new(read_half);
+
+    loop {
+        // Greeting and PacketVersions change the state, so it is taken before every packet
+        let serializer_state = match data.lock().sessions.get(&session_id) {
+            Some(session) => session.serializer_state.clone(),
+            None => return,
+        };
+
+        let contract =
+            match MySbTcpContract::deserialize(&mut socket_reader, &serializer_state).await {
+                Ok(contract) => contract,
+                Err(_) => break,
+            };
+
+        handle_packet(&data, session_id, contract);
+    }
+
+    data.lock().session_disconnected(session_id);
+}
+
+fn handle_packet(data: &Mutex<TestBrokerData>, session_id: i64, contract: MySbTcpContract) {
+    let mut write_access = data.lock();
+
+    if !matches!(contract, MySbTcpContract::Ping) {
+        write_access.received.push(contract.clone());
+    }
+
+    match contract {
+        MySbTcpContract::Ping => {
+            if let Some(session) = write_access.sessions.get(&session_id) {
+                session.send(&MySbTcpContract::Pong);
+            }
+        }
+        MySbTcpContract::Greeting { .. } | MySbTcpContract::PacketVersions { .. } => {
+            if let Some(session) = write_access.sessions.get_mut(&session_id) {
+                session.serializer_state.apply_tcp_contract(&contract);
+            }
+        }
+        MySbTcpContract::CreateTopicIfNotExists { topic_id } => {
+            write_access.create_topic_if_not_exists(&topic_id);
+        }
+        MySbTcpContract::Publish {
+            topic_id,
+            request_id,
+            data_to_publish,
+            ..
+        } => {
+            write_access.publish(&topic_id, data_to_publish);
+
+            if write_access.publish_responses_are_paused {
+                write_access
+                    .held_publish_responses
+                    .push((session_id, request_id));
+            } else if let Some(session) = write_access.sessions.get(&session_id) {
+                session.send(&MySbTcpContract::PublishResponse { request_id });
+            }
+        }
+        MySbTcpContract::Subscribe {
+            topic_id, queue_id, ..
+        } => {
+            write_access.subscribe(session_id, &topic_id, &queue_id);
+        }
+        MySbTcpContract::NewMessagesConfirmation { .. }
+        | MySbTcpContract::AllMessagesConfirmedAsFail { .. }
+        | MySbTcpContract::ConfirmSomeMessagesAsOk { .. }
+        | MySbTcpContract::IntermediaryConfirm { .. } => {
+            write_access.confirm(contract);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,371 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use my_service_bus_abstractions::{
+    publisher::MessageToPublish, queue_with_intervals::QueueIndexRange, MessageId, MySbMessage,
+};
+use tokio::{sync::mpsc::UnboundedSender, task::JoinHandle};
+
+use crate::{
+    new_messages_packet_builder::NewMessagesPacketBuilder, tcp_message_id::NEW_MESSAGES,
+    MySbSerializerState, MySbTcpContract,
+};
+
+pub struct TestBrokerSession {
+    pub sender: UnboundedSender<Vec<u8>>,
+    pub serializer_state: MySbSerializerState,
+    pub reader: Option<JoinHandle<()>>,
+}
+
+impl TestBrokerSession {
+    pub fn send(&self, contract: &MySbTcpContract) {
+        let mut payload = Vec::new();
+        contract.serialize(&mut payload, &self.serializer_state);
+        let _ = self.sender.send(payload);
+    }
+}
+
+struct TestBrokerBatch {
+    confirmation_id: i64,
+    messages: Vec<MySbMessage>,
+}
+
+struct TestBrokerQueue {
+    session_id: Option<i64>,
+    pending: VecDeque<MySbMessage>,
+    in_flight: Option<TestBrokerBatch>,
+}
+
+impl TestBrokerQueue {
+    fn redeliver(&mut self, messages: Vec<MySbMessage>) {
+        for mut message in messages.into_iter().rev() {
+            message.attempt_no += 1;
+            self.pending.push_front(message);
+        }
+    }
+}
+
+pub struct TestBrokerData {
+    pub sessions: HashMap<i64, TestBrokerSession>,
+    pub published: HashMap<String, Vec<MySbMessage>>,
+    pub confirmations: Vec<MySbTcpContract>,
+    pub received: Vec<MySbTcpContract>,
+    pub publish_responses_are_paused: bool,
+    // Session id and request id of the publishes which are not responded while paused
+    pub held_publish_responses: Vec<(i64, i64)>,
+    // Subscribes to these topic and queue ids are not acknowledged
+    pub unknown_queues: HashSet<(String, String)>,
+    queues: HashMap<(String, String), TestBrokerQueue>,
+    next_message_id: i64,
+    next_confirmation_id: i64,
+}
+
+impl TestBrokerData {
+    pub fn new() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            published: HashMap::new(),
+            confirmations: Vec::new(),
+            received: Vec::new(),
+            publish_responses_are_paused: false,
+            held_publish_responses: Vec::new(),
+            unknown_queues: HashSet::new(),
+            queues: HashMap::new(),
+            next_message_id: 0,
+            next_confirmation_id: 0,
+        }
+    }
+
+    pub fn create_topic_if_not_exists(&mut self, topic_id: &str) {
+        if !self.published.contains_key(topic_id) {
+            self.published.insert(topic_id.to_string(), Vec::new());
+        }
+    }
+
+    pub fn publish(&mut self, topic_id: &str, messages: Vec<MessageToPublish>) {
+        let mut to_publish = Vec::with_capacity(messages.len());
+
+        for message in messages {
+            to_publish.push(MySbMessage {
+                id: MessageId::new(self.next_message_id),
+                attempt_no: 0,
+                headers: message.headers,
+                content: message.content,
+            });
+
+            self.next_message_id += 1;
+        }
+
+        for ((queue_topic_id, _), queue) in self.queues.iter_mut() {
+            if queue_topic_id == topic_id {
+                queue.pending.extend(to_publish.iter().cloned());
+            }
+        }
+
+        self.published
+            .entry(topic_id.to_string())
+            .or_default()
+            .extend(to_publish);
+
+        self.deliver();
+    }
+
+    // Only messages published after the queue is created are delivered to it
+    pub fn subscribe(&mut self, session_id: i64, topic_id: &str, queue_id: &str) {
+        if self
+            .unknown_queues
+            .contains(&(topic_id.to_string(), queue_id.to_string()))
+        {
+            return;
+        }
+
+        self.create_topic_if_not_exists(topic_id);
+
+        let queue = self
+            .queues
+            .entry((topic_id.to_string(), queue_id.to_string()))
+            .or_insert_with(|| TestBrokerQueue {
+                session_id: None,
+                pending: VecDeque::new(),
+                in_flight: None,
+            });
+
+        queue.session_id = Some(session_id);
+
+        if let Some(session) = self.sessions.get(&session_id) {
+            session.send(&MySbTcpContract::SubscribeResponse {
+                topic_id: topic_id.to_string(),
+                queue_id: queue_id.to_string(),
+            });
+        }
+
+        self.deliver();
+    }
+
+    // Messages which are not confirmed are redelivered to the next subscriber
+    pub fn session_disconnected(&mut self, session_id: i64) -> Option<TestBrokerSession> {
+        for queue in self.queues.values_mut() {
+            if queue.session_id == Some(session_id) {
+                queue.session_id = None;
+
+                if let Some(in_flight) = queue.in_flight.take() {
+                    queue.redeliver(in_flight.messages);
+                }
+            }
+        }
+
+        self.sessions.remove(&session_id)
+    }
+
+    pub fn confirm(&mut self, contract: MySbTcpContract) {
+        let (topic_id, queue_id, confirmation_id) = match &contract {
+            MySbTcpContract::NewMessagesConfirmation {
+                topic_id,
+                queue_id,
+                confirmation_id,
+            } => (topic_id, queue_id, confirmation_id),
+            MySbTcpContract::AllMessagesConfirmedAsFail {
+                topic_id,
+                queue_id,
+                confirmation_id,
+            } => (topic_id, queue_id, confirmation_id),
+            MySbTcpContract::ConfirmSomeMessagesAsOk {
+                topic_id,
+                queue_id,
+                confirmation_id,
+                ..
+            } => (topic_id, queue_id, confirmation_id),
+            MySbTcpContract::IntermediaryConfirm {
+                topic_id,
+                queue_id,
+                confirmation_id,
+                ..
+            } => (topic_id, queue_id, confirmation_id),
+            _ => return,
+        };
+
+        if let Some(queue) = self
+            .queues
+            .get_mut(&(topic_id.to_string(), queue_id.to_string()))
+        {
+            let in_flight = match queue.in_flight.take() {
+                Some(in_flight) if in_flight.confirmation_id == *confirmation_id => in_flight,
+                other => {
+                    queue.in_flight = other;
+                    return;
+                }
+            };
+
+            match &contract {
+                MySbTcpContract::AllMessagesConfirmedAsFail { .. } => {
+                    queue.redeliver(in_flight.messages);
+                }
+                MySbTcpContract::ConfirmSomeMessagesAsOk { delivered, .. } => {
+                    queue.redeliver(get_not_delivered(in_flight.messages, delivered));
+                }
+                MySbTcpContract::IntermediaryConfirm { delivered, .. } => {
+                    queue.in_flight = Some(TestBrokerBatch {
+                        confirmation_id: in_flight.confirmation_id,
+                        messages: get_not_delivered(in_flight.messages, delivered),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        self.confirmations.push(contract);
+        self.deliver();
+    }
+
+    fn deliver(&mut self) {
+        for ((topic_id, queue_id), queue) in self.queues.iter_mut() {
+            if queue.in_flight.is_some() || queue.pending.len() == 0 {
+                continue;
+            }
+
+            let session = match queue.session_id.and_then(|id| self.sessions.get(&id)) {
+                Some(session) => session,
+                None => continue,
+            };
+
+            let confirmation_id = self.next_confirmation_id;
+            self.next_confirmation_id += 1;
+
+            let mut builder = NewMessagesPacketBuilder::new(
+                topic_id,
+                queue_id,
+                confirmation_id,
+                session.serializer_state.get(NEW_MESSAGES),
+            );
+
+            let messages: Vec<MySbMessage> = queue.pending.drain(..).collect();
+
+            for message in messages.iter() {
+                builder.append_packet(message);
+            }
+
+            session.send(&builder.get_result());
+
+            queue.in_flight = Some(TestBrokerBatch {
+                confirmation_id,
+                messages,
+            });
+        }
+    }
+}
+
+fn get_not_delivered(
+    messages: Vec<MySbMessage>,
+    delivered: &[QueueIndexRange<i64>],
+) -> Vec<MySbMessage> {
+    messages
+        .into_iter()
+        .filter(|itm| {
+            let id = itm.id.get_value();
+            !delivered
+                .iter()
+                .any(|range| range.from_id <= id && id <= range.to_id)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use my_service_bus_abstractions::publisher::MessageToPublish;
+    use my_tcp_sockets::socket_reader::SocketReaderInMem;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::{TestBrokerData, TestBrokerSession};
+    use crate::{MySbSerializerState, MySbTcpContract};
+
+    fn add_session(data: &mut TestBrokerData, session_id: i64) -> UnboundedReceiver<Vec<u8>> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        data.sessions.insert(
+            session_id,
+            TestBrokerSession {
+                sender,
+                serializer_state: MySbSerializerState::new(crate::DEFAULT_TCP_PROTOCOL_VERSION),
+                reader: None,
+            },
+        );
+
+        receiver
+    }
+
+    async fn next_packet(receiver: &mut UnboundedReceiver<Vec<u8>>) -> MySbTcpContract {
+        let payload = receiver.try_recv().unwrap();
+        let mut socket_reader = SocketReaderInMem::new(payload);
+        let state = MySbSerializerState::new(crate::DEFAULT_TCP_PROTOCOL_VERSION);
+
+        MySbTcpContract::deserialize(&mut socket_reader, &state)
+            .await
+            .unwrap()
+    }
+
+    fn get_delivery(contract: MySbTcpContract) -> (i64, Vec<i32>) {
+        match contract {
+            MySbTcpContract::NewMessages(model) => (
+                model.confirmation_id,
+                model.messages.iter().map(|itm| itm.attempt_no).collect(),
+            ),
+            _ => panic!("NewMessages is expected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_not_confirmed_messages_are_redelivered() {
+        let mut data = TestBrokerData::new();
+        let mut receiver = add_session(&mut data, 1);
+
+        data.subscribe(1, "topic", "queue");
+
+        assert!(matches!(
+            next_packet(&mut receiver).await,
+            MySbTcpContract::SubscribeResponse { .. }
+        ));
+
+        data.publish("topic", vec![MessageToPublish::new(vec![1])]);
+
+        let (confirmation_id, attempts) = get_delivery(next_packet(&mut receiver).await);
+        assert_eq!(vec![0], attempts);
+
+        data.confirm(MySbTcpContract::AllMessagesConfirmedAsFail {
+            topic_id: "topic".to_string(),
+            queue_id: "queue".to_string(),
+            confirmation_id,
+        });
+
+        let (confirmation_id, attempts) = get_delivery(next_packet(&mut receiver).await);
+        assert_eq!(vec![1], attempts);
+
+        data.confirm(MySbTcpContract::NewMessagesConfirmation {
+            topic_id: "topic".to_string(),
+            queue_id: "queue".to_string(),
+            confirmation_id,
+        });
+
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(2, data.confirmations.len());
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_messages_are_redelivered_after_disconnect() {
+        let mut data = TestBrokerData::new();
+        let mut receiver = add_session(&mut data, 1);
+
+        data.subscribe(1, "topic", "queue");
+        next_packet(&mut receiver).await;
+
+        data.publish("topic", vec![MessageToPublish::new(vec![1])]);
+        next_packet(&mut receiver).await;
+
+        data.session_disconnected(1);
+
+        let mut receiver = add_session(&mut data, 2);
+        data.subscribe(2, "topic", "queue");
+        next_packet(&mut receiver).await;
+
+        let (_, attempts) = get_delivery(next_packet(&mut receiver).await);
+        assert_eq!(vec![1], attempts);
+    }
+}